@@ -1,10 +1,12 @@
 use std::{
     collections::BTreeSet,
-    hash::Hash,
+    hash::{Hash, Hasher},
 };
 
 use anyhow::{Result, bail, Context};
 
+use reqwest::Url;
+
 use select::node::Node;
 
 use serde::{Deserialize, Serialize};
@@ -14,7 +16,43 @@ use serde::{Deserialize, Serialize};
 pub struct Anime {
     pub name: String,
     pub url: String,
-    pub episodes: Vec<Episode>
+    #[serde(default)]
+    pub seasons: Vec<Season>,
+    #[serde(default)]
+    pub films: Vec<Episode>,
+    #[serde(default)]
+    pub ovas: Vec<Episode>,
+    #[serde(default)]
+    pub metadata: Metadata,
+    #[serde(default)]
+    pub updated_at: Option<u64>,
+    #[serde(default)]
+    pub provider: String,
+    /// The site address the URL was cached under, so that it can be pointed
+    /// at another one later.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+}
+
+
+/// Details from the anime page. Anime that were only seen in the catalogue
+/// have nothing but the poster here until their page is opened.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Metadata {
+    pub description: String,
+    pub genres: Vec<String>,
+    pub years: Vec<u32>,
+    pub age_rating: Option<String>,
+    pub poster: Option<String>,
+    pub alt_titles: Vec<String>,
+}
+
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Season {
+    pub number: u32,
+    pub episodes: Vec<Episode>,
 }
 
 
@@ -31,20 +69,167 @@ impl Default for Anime {
 
 impl Anime {
     pub fn new<Name: ToString, URL: ToString>(name: Name, url: URL, episodes: Vec<Episode>) -> Self {
-        Self {
+        let mut anime: Self = Self {
             name: name.to_string(),
             url: url.to_string(),
-            episodes,
+            seasons: Vec::new(),
+            films: Vec::new(),
+            ovas: Vec::new(),
+            metadata: Metadata::default(),
+            updated_at: None,
+            provider: String::new(),
+            base_url: None,
+        };
+        for episode in episodes {
+            anime.add_episode(episode);
+        }
+        anime
+    }
+
+    /// Puts the episode into its season, or among the films or OVAs.
+    pub fn add_episode(&mut self, episode: Episode) {
+        match episode.kind {
+            EpisodeKind::Episode => match self.seasons.iter_mut().find(|s| s.number == episode.season) {
+                Some(season) => season.episodes.push(episode),
+                None => self.seasons.push(Season {
+                    number: episode.season,
+                    episodes: vec![episode],
+                }),
+            },
+            EpisodeKind::Film => self.films.push(episode),
+            EpisodeKind::Ova => self.ovas.push(episode),
         }
     }
+
+    /// Every episode in order: the seasons, then films, then OVAs.
+    pub fn episodes(&self) -> Vec<Episode> {
+        self.parts()
+            .into_iter()
+            .flat_map(|(_, episodes)| episodes)
+            .collect()
+    }
+
+    pub fn episodes_mut(&mut self) -> impl Iterator<Item = &mut Episode> {
+        self.seasons
+            .iter_mut()
+            .flat_map(|season| season.episodes.iter_mut())
+            .chain(self.films.iter_mut())
+            .chain(self.ovas.iter_mut())
+    }
+
+    /// The seasons, films and OVAs with their names, empty ones left out.
+    pub fn parts(&self) -> Vec<(String, Vec<Episode>)> {
+        let mut parts: Vec<(String, Vec<Episode>)> = self.seasons
+            .iter()
+            .map(|season| (format!("Season {}", season.number), season.episodes.clone()))
+            .collect();
+        if !self.films.is_empty() {
+            parts.push((String::from("Films"), self.films.clone()));
+        }
+        if !self.ovas.is_empty() {
+            parts.push((String::from("OVA"), self.ovas.clone()));
+        }
+        parts
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seasons.is_empty() && self.films.is_empty() && self.ovas.is_empty()
+    }
+
+    /// The last path segment of the anime URL, e.g. `naruuto` for
+    /// `https://jut.su/naruuto/`.
+    pub fn slug(&self) -> String {
+        self.url
+            .trim_end_matches('/')
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .to_string()
+    }
 }
 
 
-#[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+impl Metadata {
+    /// Years and genres on one line, for lists of anime.
+    pub fn short(&self) -> String {
+        let years: Vec<String> = self.years.iter().map(|y| y.to_string()).collect();
+        [years.join(", "), self.genres.join(", ")]
+            .into_iter()
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<String>>()
+            .join("; ")
+    }
+
+    /// The known details, one per line, with the description last.
+    pub fn describe(&self) -> String {
+        let mut result: String = String::new();
+        if !self.alt_titles.is_empty() {
+            result += format!("Also known as: {}\n", self.alt_titles.join(" / ")).as_str();
+        }
+        if !self.years.is_empty() {
+            let years: Vec<String> = self.years.iter().map(|y| y.to_string()).collect();
+            result += format!("Years: {}\n", years.join(", ")).as_str();
+        }
+        if !self.genres.is_empty() {
+            result += format!("Genres: {}\n", self.genres.join(", ")).as_str();
+        }
+        if let Some(age_rating) = &self.age_rating {
+            result += format!("Age rating: {age_rating}\n").as_str();
+        }
+        if let Some(poster) = &self.poster {
+            result += format!("Poster: {poster}\n").as_str();
+        }
+        if !self.description.is_empty() {
+            result += format!("\n{}\n", self.description).as_str();
+        }
+        result
+    }
+}
+
+
+/// Episodes compare by their `key`, so an episode is the same before and
+/// after its video sources are resolved.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Episode {
     pub name: String,
     pub url: String,
-    pub quality: BTreeSet<Quality>
+    #[serde(default)]
+    pub provider: String,
+    #[serde(default)]
+    pub kind: EpisodeKind,
+    #[serde(default)]
+    pub season: u32,
+    #[serde(default)]
+    pub number: Option<u32>,
+    pub quality: BTreeSet<Quality>,
+    /// The site address the URL was cached under, so that it can be pointed
+    /// at another one later.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+}
+
+
+/// What identifies an episode: where it comes from and its place in the anime.
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct EpisodeKey {
+    pub provider: String,
+    pub anime: String,
+    pub kind: EpisodeKind,
+    pub season: u32,
+    pub number: Option<u32>,
+    /// The page name, `special` in `/anime/special.html`, for episodes
+    /// whose URL has no number to tell them apart.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page: Option<String>,
+}
+
+
+#[derive(Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum EpisodeKind {
+    #[default]
+    Episode,
+    Film,
+    Ova,
 }
 
 
@@ -65,79 +250,303 @@ impl Episode {
         url: URL,
         quality: BTreeSet<Quality>
     ) -> Self {
+        let url: String = url.to_string();
+        let (kind, season, number) = Self::parse_url(url.as_str());
         Self {
             name: name.to_string(),
-            url: url.to_string(),
+            url,
+            provider: String::new(),
+            kind,
+            season,
+            number,
             quality,
+            base_url: None,
+        }
+    }
+
+    pub fn key(&self) -> EpisodeKey {
+        EpisodeKey {
+            provider: self.provider.clone(),
+            anime: self.anime_slug(),
+            kind: self.kind,
+            season: self.season,
+            number: self.number,
+            page: match self.number {
+                Some(_) => None,
+                None => Some(self.page_name()),
+            },
+        }
+    }
+
+    fn path_parts(&self) -> Vec<&str> {
+        self.url
+            .split(['?', '#'])
+            .next()
+            .unwrap_or_default()
+            .split('/')
+            .filter(|part| !part.is_empty())
+            .collect()
+    }
+
+    /// The last part of the URL, `episode-1` in `/naruto/season-1/episode-1.html`.
+    fn page_name(&self) -> String {
+        self.path_parts()
+            .last()
+            .map(|part| part.trim_end_matches(".html").to_string())
+            .unwrap_or_default()
+    }
+
+    /// The anime part of the URL, `naruto` in `/naruto/season-1/episode-1.html`.
+    fn anime_slug(&self) -> String {
+        let mut parts: Vec<&str> = self.path_parts();
+        parts.pop();
+        if parts.last().is_some_and(|part| part.starts_with("season-")) {
+            parts.pop();
+        }
+        parts.last().unwrap_or(&"").to_string()
+    }
+
+    /// Reads the kind, season and number from URLs like
+    /// `/anime/season-2/episode-5.html`, `/anime/film-1.html` or `/anime/ova-1.html`.
+    /// Single-season anime have no `season-N` part and count as season 1,
+    /// films and OVAs belong to no season and get 0.
+    fn parse_url(url: &str) -> (EpisodeKind, u32, Option<u32>) {
+        if let Some(number) = url_number(url, "film-") {
+            return (EpisodeKind::Film, 0, Some(number));
+        }
+        if let Some(number) = url_number(url, "ova-") {
+            return (EpisodeKind::Ova, 0, Some(number));
+        }
+        (
+            EpisodeKind::Episode,
+            url_number(url, "season-").unwrap_or(1),
+            url_number(url, "episode-"),
+        )
+    }
+}
+
+
+impl EpisodeKey {
+    /// The episode part of the key, e.g. `s02e05`, `film 1` or `ova 1`,
+    /// or `s01 special` for an episode known only by its page name.
+    pub fn label(&self) -> String {
+        let number: String = match (self.number, &self.page) {
+            (Some(number), _) => number.to_string(),
+            (None, Some(page)) if self.kind == EpisodeKind::Episode => return format!("s{:02} {page}", self.season),
+            (None, Some(page)) => page.clone(),
+            (None, None) => String::from("?"),
+        };
+        match self.kind {
+            EpisodeKind::Episode => format!("s{:02}e{:0>2}", self.season, number),
+            EpisodeKind::Film => format!("film {number}"),
+            EpisodeKind::Ova => format!("ova {number}"),
         }
     }
 }
 
 
+impl PartialEq for Episode {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+
+impl Eq for Episode {}
+
+
+impl Hash for Episode {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+
+fn url_number(url: &str, prefix: &str) -> Option<u32> {
+    url.split('/')
+        .filter_map(|part| part.strip_prefix(prefix))
+        .next_back()?
+        .trim_end_matches(".html")
+        .parse()
+        .ok()
+}
+
+
+/// One video source of an episode. Sources sort by resolution, so the
+/// best one is the last in an episode's `quality` set.
 #[derive(Clone, Serialize, Deserialize, PartialEq, Eq, Hash, Ord, PartialOrd)]
-pub enum Quality {
-    Q360P(String),
-    Q480P(String),
-    Q720P(String),
-    Q1080P(String),
+pub struct Quality {
+    pub resolution: u32,
+    pub url: String,
+    #[serde(default)]
+    pub bitrate: Option<u32>,
+    #[serde(default)]
+    pub codec: Option<String>,
 }
 
 
 impl Default for Quality {
     fn default() -> Self {
-        Self::arr()[0].clone()
+        Self::new(720)
     }
 }
 
 
 impl Quality {
-    pub fn from(node: Node) -> Result<Self> {
-        let quality: &str = node
+    /// A quality without a source, used to ask for a resolution.
+    pub fn new(resolution: u32) -> Self {
+        Self {
+            resolution,
+            url: String::new(),
+            bitrate: None,
+            codec: None,
+        }
+    }
+
+    /// Reads a `<source src="..." res="720">` tag. The bitrate comes from a
+    /// `bitrate` attribute and the codec from `type="video/mp4; codecs=..."`,
+    /// when the page has them.
+    pub fn from(node: Node, base_url: &Url) -> Result<Self> {
+        let resolution: &str = node
             .attr("res")
+            .or_else(|| node.attr("label"))
             .context("Error when parsing anime quality")?;
-        
-        let url: String = node
-            .attr("src")
-            .context("")?
+
+        let url: String = base_url
+            .join(node.attr("src").context("Error when parsing the video link")?)?
             .to_string();
-        
-        Ok(match quality {
-            "360" => Self::Q360P(url),
-            "480" => Self::Q480P(url),
-            "720" => Self::Q720P(url),
-            "1080" => Self::Q1080P(url),
-            _ => bail!("An error in correlating anime quality and available quality"),
+
+        Ok(Self {
+            resolution: Self::from_name(resolution)?.resolution,
+            url,
+            bitrate: node.attr("bitrate").and_then(|bitrate| bitrate.trim().parse().ok()),
+            codec: node
+                .attr("type")
+                .and_then(|t| t.split_once("codecs="))
+                .map(|(_, codecs)| codecs.trim_matches(['"', '\'', ' ']).to_string()),
         })
     }
 
-    pub fn arr() -> [Self; 4] {
-        [
-            Self::Q360P(String::from("360p")),
-            Self::Q480P(String::from("480p")),
-            Self::Q720P(String::from("720p")),
-            Self::Q1080P(String::from("1080p")),
-        ]
-    }
-    
+    /// Resolutions offered as the default quality.
+    pub fn common() -> [Self; 7] {
+        [240, 360, 480, 720, 1080, 1440, 2160].map(Self::new)
+    }
+
+    /// Parses `720`, `720p` or `720P`.
+    pub fn from_name(name: &str) -> Result<Self> {
+        let name: &str = name.trim().trim_end_matches(['p', 'P']);
+        match name.parse::<u32>() {
+            Ok(resolution) if resolution > 0 => Ok(Self::new(resolution)),
+            _ => bail!("Unknown quality: {name}"),
+        }
+    }
+
     pub fn equal(&self, quality: &Quality) -> bool {
-        self.empty() == quality.empty()
+        self.resolution == quality.resolution
     }
-    
-    pub fn empty(&self) -> Self {
-        match self {
-            Self::Q360P(_) => Self::Q360P(String::new()),
-            Self::Q480P(_) => Self::Q480P(String::new()),
-            Self::Q720P(_) => Self::Q720P(String::new()),
-            Self::Q1080P(_) => Self::Q1080P(String::new()),
+
+    pub fn name(&self) -> String {
+        format!("{}p", self.resolution)
+    }
+
+    /// The resolution with the bitrate and codec when they are known.
+    pub fn describe(&self) -> String {
+        let mut result: String = self.name();
+        if let Some(bitrate) = self.bitrate {
+            result += format!(", {bitrate} kbps").as_str();
         }
+        if let Some(codec) = &self.codec {
+            result += format!(", {codec}").as_str();
+        }
+        result
     }
-    
+
     pub fn val(&self) -> String {
+        self.url.clone()
+    }
+}
+
+
+/// What to download when an episode doesn't offer the requested resolution.
+#[derive(Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum QualityFallback {
+    /// Only the requested resolution, the episode fails without it.
+    Exact,
+    /// The best resolution not above the requested one.
+    #[default]
+    AtOrBelow,
+    /// The best resolution offered.
+    Best,
+    /// The closest resolution, the higher one on a tie.
+    Nearest,
+}
+
+
+impl QualityFallback {
+    pub fn arr() -> [Self; 4] {
+        [Self::Exact, Self::AtOrBelow, Self::Best, Self::Nearest]
+    }
+
+    pub fn from_name(name: &str) -> Result<Self> {
+        let name: &str = name.trim();
+        Self::arr()
+            .into_iter()
+            .find(|f| f.name() == name)
+            .context(format!("Unknown quality fallback: {name} (expected exact, at-or-below, best or nearest)"))
+    }
+
+    pub fn name(&self) -> String {
+        String::from(match self {
+            Self::Exact => "exact",
+            Self::AtOrBelow => "at-or-below",
+            Self::Best => "best",
+            Self::Nearest => "nearest",
+        })
+    }
+
+    /// Picks a source from `offered` for the `wanted` resolution.
+    pub fn pick<'a>(&self, wanted: &Quality, offered: &'a BTreeSet<Quality>) -> Option<&'a Quality> {
+        if let Some(exact) = offered.iter().rev().find(|q| q.equal(wanted)) {
+            return Some(exact);
+        }
         match self {
-            Self::Q360P(url) => url,
-            Self::Q480P(url) => url,
-            Self::Q720P(url) => url,
-            Self::Q1080P(url) => url,
-        }.clone()
+            Self::Exact => None,
+            Self::AtOrBelow => offered.iter().rev().find(|q| q.resolution <= wanted.resolution),
+            Self::Best => offered.last(),
+            Self::Nearest => offered
+                .iter()
+                .rev()
+                .min_by_key(|q| q.resolution.abs_diff(wanted.resolution)),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn episode(url: &str) -> Episode {
+        Episode::new("", url, BTreeSet::new())
+    }
+
+    #[test]
+    fn episodes_without_numbers_keep_apart() {
+        let special: Episode = episode("https://jut.su/naruto/special.html");
+        let recap: Episode = episode("https://jut.su/naruto/recap.html");
+        assert!(special.number.is_none());
+        assert!(special != recap);
+        assert_eq!(special.key().label(), "s01 special");
+        assert!(special == episode("http://127.0.0.1:8080/naruto/special.html"));
+    }
+
+    #[test]
+    fn numbered_episodes_key_by_number() {
+        let first: Episode = episode("https://jut.su/naruto/season-2/episode-5.html");
+        assert_eq!(first.key().page, None);
+        assert_eq!(first.key().label(), "s02e05");
+        assert_eq!(episode("https://jut.su/naruto/film-1.html").key().label(), "film 1");
+        assert!(first != episode("https://jut.su/bleach/season-2/episode-5.html"));
     }
 }
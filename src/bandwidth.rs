@@ -0,0 +1,240 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+
+use tokio::time::{sleep, Instant};
+
+
+/// Bytes per second, `None` for no limit.
+pub type Rate = Option<u64>;
+
+
+/// Bandwidth limits for video downloads: one shared by every stream and an
+/// optional cap on each download. Clones share the limits, so changing them
+/// slows down or speeds up the downloads that are already running.
+#[derive(Clone, Default)]
+pub struct Bandwidth {
+    total: Arc<Mutex<Bucket>>,
+    per_download: Arc<Mutex<Option<u64>>>,
+}
+
+
+/// The per-download side of `Bandwidth`, one for every episode.
+pub struct DownloadLimit {
+    bandwidth: Bandwidth,
+    bucket: Mutex<Bucket>,
+}
+
+
+/// A token bucket holding up to a second's worth of bytes. Taking more than
+/// it holds leaves it in debt, which the caller sleeps off, so concurrent
+/// streams queue up behind each other instead of bursting together.
+struct Bucket {
+    rate: Option<u64>,
+    tokens: f64,
+    updated: Instant,
+}
+
+
+impl Default for Bucket {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+
+impl Bandwidth {
+    /// Replaces both limits, in bytes per second, `None` for no limit.
+    pub fn set_limits(&self, total: Rate, per_download: Rate) {
+        if let Ok(mut bucket) = self.total.lock() {
+            bucket.set_rate(total);
+        }
+        if let Ok(mut limit) = self.per_download.lock() {
+            *limit = per_download;
+        }
+    }
+
+    pub fn download(&self) -> DownloadLimit {
+        DownloadLimit {
+            bandwidth: self.clone(),
+            bucket: Mutex::new(Bucket::new(None)),
+        }
+    }
+
+    fn per_download(&self) -> Option<u64> {
+        self.per_download.lock().map(|limit| *limit).unwrap_or(None)
+    }
+}
+
+
+impl DownloadLimit {
+    /// Waits until `bytes` more fit into both limits.
+    pub async fn take(&self, bytes: u64) {
+        let per_download: Option<u64> = self.bandwidth.per_download();
+        let own: Duration = match self.bucket.lock() {
+            Ok(mut bucket) => {
+                bucket.set_rate(per_download);
+                bucket.take(bytes)
+            },
+            Err(_) => Duration::ZERO,
+        };
+        let total: Duration = match self.bandwidth.total.lock() {
+            Ok(mut bucket) => bucket.take(bytes),
+            Err(_) => Duration::ZERO,
+        };
+
+        let wait: Duration = own.max(total);
+        if !wait.is_zero() {
+            sleep(wait).await;
+        }
+    }
+}
+
+
+impl Bucket {
+    fn new(rate: Option<u64>) -> Self {
+        Self {
+            rate,
+            tokens: rate.unwrap_or(0) as f64,
+            updated: Instant::now(),
+        }
+    }
+
+    fn set_rate(&mut self, rate: Option<u64>) {
+        if self.rate != rate {
+            *self = Self::new(rate);
+        }
+    }
+
+    /// Takes `bytes` out of the bucket and returns how long to wait for them.
+    fn take(&mut self, bytes: u64) -> Duration {
+        let Some(rate) = self.rate else {
+            return Duration::ZERO;
+        };
+
+        let now: Instant = Instant::now();
+        let refill: f64 = now.duration_since(self.updated).as_secs_f64() * rate as f64;
+        self.tokens = (self.tokens + refill).min(rate as f64) - bytes as f64;
+        self.updated = now;
+
+        match self.tokens < 0.0 {
+            true => Duration::from_secs_f64(-self.tokens / rate as f64),
+            false => Duration::ZERO,
+        }
+    }
+}
+
+
+/// Parses a rate such as `500K`, `5M` or `1.5MiB/s` into bytes per second,
+/// with binary units; `none`, `0` or nothing mean no limit.
+pub fn parse_rate(value: &str) -> Result<Rate> {
+    let value: String = value.trim().to_lowercase();
+    let value: &str = value.trim_end_matches("/s").trim_end_matches("ib").trim_end_matches('b').trim();
+    if value.is_empty() || value == "none" {
+        return Ok(None);
+    }
+
+    let (number, unit) = value.split_at(value.find(|c: char| c.is_alphabetic()).unwrap_or(value.len()));
+    let multiplier: f64 = match unit.trim() {
+        "" => 1.0,
+        "k" => 1024.0,
+        "m" => 1024.0 * 1024.0,
+        "g" => 1024.0 * 1024.0 * 1024.0,
+        _ => bail!("Unknown rate unit: {unit} (expected K, M or G)"),
+    };
+    let number: f64 = number.trim().parse().context(format!("Expected a rate such as 500K or 5M, got {value}"))?;
+    if number < 0.0 || !number.is_finite() {
+        bail!("The rate can't be negative");
+    }
+
+    match (number * multiplier) as u64 {
+        0 => Ok(None),
+        rate => Ok(Some(rate)),
+    }
+}
+
+
+pub fn format_rate(rate: Rate) -> String {
+    match rate {
+        Some(rate) if rate >= 1024 * 1024 => format!("{:.1} MiB/s", rate as f64 / (1024.0 * 1024.0)),
+        Some(rate) if rate >= 1024 => format!("{:.1} KiB/s", rate as f64 / 1024.0),
+        Some(rate) => format!("{rate} B/s"),
+        None => String::from("none"),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rates() {
+        assert_eq!(parse_rate("500K").unwrap(), Some(500 * 1024));
+        assert_eq!(parse_rate("5M").unwrap(), Some(5 * 1024 * 1024));
+        assert_eq!(parse_rate("1.5MiB/s").unwrap(), Some(1024 * 1024 * 3 / 2));
+        assert_eq!(parse_rate(" 2 gb ").unwrap(), Some(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_rate("800").unwrap(), Some(800));
+        assert_eq!(parse_rate("800B/s").unwrap(), Some(800));
+    }
+
+    #[test]
+    fn parses_no_limit() {
+        assert_eq!(parse_rate("none").unwrap(), None);
+        assert_eq!(parse_rate("None").unwrap(), None);
+        assert_eq!(parse_rate("0").unwrap(), None);
+        assert_eq!(parse_rate("").unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_invalid_rates() {
+        assert_eq!(parse_rate("5x").err().unwrap().to_string(), "Unknown rate unit: x (expected K, M or G)");
+        assert_eq!(parse_rate("-5M").err().unwrap().to_string(), "The rate can't be negative");
+        assert_eq!(parse_rate("fast").err().unwrap().to_string(), "Unknown rate unit: fast (expected K, M or G)");
+        assert_eq!(parse_rate("1.2.3K").err().unwrap().to_string(), "Expected a rate such as 500K or 5M, got 1.2.3k");
+    }
+
+    #[test]
+    fn formats_rates() {
+        assert_eq!(format_rate(Some(5 * 1024 * 1024)), "5.0 MiB/s");
+        assert_eq!(format_rate(Some(1536)), "1.5 KiB/s");
+        assert_eq!(format_rate(Some(800)), "800 B/s");
+        assert_eq!(format_rate(None), "none");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bucket_allows_a_second_then_waits() {
+        let mut bucket: Bucket = Bucket::new(Some(1000));
+        // A full bucket holds a second's worth
+        assert_eq!(bucket.take(1000), Duration::ZERO);
+        // Past that, the debt is slept off at the rate
+        assert_eq!(bucket.take(500), Duration::from_millis(500));
+
+        tokio::time::advance(Duration::from_millis(1500)).await;
+        assert_eq!(bucket.take(1000), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bucket_refills_up_to_a_second() {
+        let mut bucket: Bucket = Bucket::new(Some(1000));
+        bucket.take(1000);
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert_eq!(bucket.take(1000), Duration::ZERO);
+        assert_eq!(bucket.take(1000), Duration::from_secs(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unlimited_and_changed_rates() {
+        let mut bucket: Bucket = Bucket::new(None);
+        assert_eq!(bucket.take(u32::MAX as u64), Duration::ZERO);
+
+        bucket.set_rate(Some(100));
+        assert_eq!(bucket.take(200), Duration::from_secs(1));
+        // Setting the same rate keeps the debt
+        bucket.set_rate(Some(100));
+        assert_eq!(bucket.take(100), Duration::from_secs(2));
+    }
+}
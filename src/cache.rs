@@ -1,18 +1,49 @@
 use std::{
+    collections::{BTreeSet, VecDeque},
     fs::{File, create_dir_all},
-    path::Path,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use anyhow::{Result, Context};
+use anyhow::{anyhow, bail, Result, Context};
 
-use indicatif::ProgressBar;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+
+use tokio::{task::JoinHandle, time::sleep};
+
+use futures::{stream, StreamExt};
+
+use reqwest::Url;
 
 use serde::{Deserialize, Serialize};
 
-use crate::anime::{Anime, Episode, Quality};
-use crate::net::Net;
-use crate::parser::Parser;
-use crate::settings::Settings;
+use crate::anime::{Anime, Episode, Quality, QualityFallback};
+use crate::bandwidth::{format_rate, Bandwidth, Rate};
+use crate::history::{checksum, find_parts, FileState, History, HistoryEntry, PendingDownload, Unfinished};
+use crate::net::{part_path, segments_path, Net};
+use crate::provider::{self, Provider};
+use crate::search::SearchQuery;
+use crate::settings::{self, Settings};
+use crate::template::TemplateFields;
+use crate::transport::TransportMode;
+
+
+const CATALOGUE_CONCURRENCY: usize = 4;
+
+/// How often a running batch looks for bandwidth limits saved by another instance.
+const LIMITS_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+
+/// Command line options that take precedence over the saved settings.
+#[derive(Clone, Default)]
+pub struct Overrides {
+    pub base_url: Option<Url>,
+    pub limit_rate: Option<Rate>,
+    pub download_limit_rate: Option<Rate>,
+    pub retries: Option<u32>,
+    pub transport: TransportMode,
+}
 
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -20,19 +51,29 @@ pub struct Cache {
     #[serde(skip_serializing, skip_deserializing)]
     net: Net,
     #[serde(skip_serializing, skip_deserializing)]
-    parser: Parser,
+    providers: Vec<Arc<dyn Provider>>,
+    #[serde(skip_serializing, skip_deserializing)]
+    overrides: Overrides,
+    #[serde(skip_serializing, skip_deserializing)]
+    history: Arc<Mutex<History>>,
+    #[serde(skip_serializing, skip_deserializing)]
+    bandwidth: Bandwidth,
     path: String,
+    #[serde(deserialize_with = "settings::deserialize")]
     pub settings: Settings,
     pages: u64,
+    #[serde(default)]
+    pub last_refresh: Option<u64>,
     pub anime: Vec<Anime>,
 }
 
 
 impl Default for Cache {
     fn default() -> Self {
+        let net: Net = Net::default();
         Self::new(
-            Net::default(),
-            Parser::default(),
+            net.clone(),
+            provider::all(&net),
             "./data/cache.json",
             Settings::default(),
             0,
@@ -45,7 +86,7 @@ impl Default for Cache {
 impl Cache {
     pub fn new<P: ToString>(
         net: Net,
-        parser: Parser,
+        providers: Vec<Arc<dyn Provider>>,
         path: P,
         settings: Settings,
         pages: u64,
@@ -53,41 +94,151 @@ impl Cache {
     ) -> Self {
         Self {
             net,
-            parser,
+            providers,
+            overrides: Overrides::default(),
+            history: Arc::default(),
+            bandwidth: Bandwidth::default(),
             path: path.to_string(),
             settings,
             pages,
+            last_refresh: None,
             anime,
         }
     }
     
     pub fn load(&mut self) -> Result<()> {
+        let overrides: Overrides = self.overrides.clone();
+        *self = self.read()?;
+        self.overrides = overrides;
+        self.history = Arc::new(Mutex::new(History::load(self.folder()?.join("history.json"))?));
+        self.apply_settings()
+    }
+
+    pub fn history(&self) -> Result<Vec<HistoryEntry>> {
+        Ok(self.lock_history()?.entries.clone())
+    }
+
+    fn lock_history(&self) -> Result<MutexGuard<'_, History>> {
+        self.history.lock().map_err(|_| anyhow!("The download history is unavailable"))
+    }
+
+    /// The `.part` files in the download directory.
+    pub fn unfinished(&self) -> Result<Vec<Unfinished>> {
+        let history: MutexGuard<History> = self.lock_history()?;
+        Ok(find_parts(Path::new(&self.settings.download_dir))
+            .into_iter()
+            .map(|part| Unfinished {
+                pending: history.pending.iter().find(|p| part_path(&p.path) == part).cloned(),
+                part,
+            })
+            .collect())
+    }
+
+    /// Continues every unfinished download that was recorded, returning the
+    /// number of episodes that failed again.
+    pub async fn resume_unfinished(&mut self) -> Result<usize> {
+        let mut failed: usize = 0;
+        let mut pending: Vec<PendingDownload> = self.unfinished()?
+            .into_iter()
+            .filter_map(|unfinished| unfinished.pending)
+            .collect();
+
+        while let Some(first) = pending.first().cloned() {
+            let (batch, rest): (Vec<PendingDownload>, Vec<PendingDownload>) = pending
+                .into_iter()
+                .partition(|p| p.anime_url == first.anime_url && p.quality.equal(&first.quality));
+            pending = rest;
+
+            let mut anime: Anime = Anime::new(first.anime, first.anime_url, Vec::default());
+            anime.provider = first.provider;
+            let episodes: Vec<Episode> = batch.into_iter().map(|p| p.episode).collect();
+            failed += self.download_episodes(
+                &anime,
+                episodes,
+                first.quality,
+                QualityFallback::Exact,
+                self.settings.default_concurrency,
+            ).await?;
+        }
+        Ok(failed)
+    }
+
+    /// Deletes every `.part` file and forgets the downloads they belong to.
+    pub fn delete_unfinished(&self) -> Result<usize> {
+        let unfinished: Vec<Unfinished> = self.unfinished()?;
+        let mut history: MutexGuard<History> = self.lock_history()?;
+        for entry in &unfinished {
+            std::fs::remove_file(&entry.part)?;
+            std::fs::remove_file(segments_path(&entry.part)).ok();
+            if let Some(pending) = &entry.pending {
+                history.forget(&pending.path);
+            }
+        }
+        history.save()?;
+        Ok(unfinished.len())
+    }
+
+    fn read(&self) -> Result<Self> {
         if !self.folder()?.exists() {
             create_dir_all(self.folder()?)?;
-            *self = Self::default();
-            return Ok(());
+            return Ok(Self::default());
         }
 
         if !self.file().exists() {
             File::create(self.file())?;
-            *self = Self::default();
-            return Ok(());
+            return Ok(Self::default());
         }
 
         let file: File = File::open(self.file())?;
         
-        *self = match serde_json::from_reader::<File, Self>(file) {
-            Ok(mut cache) => {
-                cache.net = Net::default();
-                cache.parser = Parser::default();
-                cache
-            },
-            Err(_) => Self::default(),
-        };
-        
+        Ok(serde_json::from_reader::<File, Self>(file).unwrap_or_default())
+    }
+
+    /// Sets options that apply until the program exits without being
+    /// written to the settings.
+    pub fn set_overrides(&mut self, overrides: Overrides) {
+        self.overrides = overrides;
+    }
+
+    pub fn apply_settings(&mut self) -> Result<()> {
+        let mut settings: Settings = self.settings.clone();
+        if let Some(base_url) = &self.overrides.base_url {
+            settings.base_url = base_url.to_string();
+        }
+        if let Some(rate) = self.overrides.limit_rate {
+            settings.limit_rate = rate;
+        }
+        if let Some(rate) = self.overrides.download_limit_rate {
+            settings.download_limit_rate = rate;
+        }
+        if let Some(retries) = self.overrides.retries {
+            settings.retry_attempts = retries;
+        }
+
+        self.bandwidth.set_limits(settings.limit_rate, settings.download_limit_rate);
+        self.net = Net::from_settings(&settings, self.overrides.transport.clone(), self.bandwidth.clone())?;
+        self.providers = provider::all(&self.net);
         Ok(())
     }
 
+    /// The provider whose catalogue `full_update` and `incremental_update` fetch.
+    fn catalogue_provider(&self) -> Result<Arc<dyn Provider>> {
+        self.providers.first().cloned().context("No anime providers are available")
+    }
+
+    /// The provider an anime came from. Entries cached before providers were
+    /// recorded belong to the catalogue provider.
+    fn provider_for(&self, anime: &Anime) -> Result<Arc<dyn Provider>> {
+        if anime.provider.is_empty() {
+            return self.catalogue_provider();
+        }
+        self.providers
+            .iter()
+            .find(|p| p.id() == anime.provider)
+            .cloned()
+            .context(format!("Unknown anime provider: {}", anime.provider))
+    }
+
     pub fn update(&mut self) -> Result<()> {
         if !self.folder()?.exists() {
             create_dir_all(self.folder()?)?;
@@ -101,50 +252,237 @@ impl Cache {
         Ok(())
     }
     
+    /// Re-fetches the whole catalogue, keeping up to `CATALOGUE_CONCURRENCY`
+    /// page requests in flight and parsing every page as it arrives, in order.
     pub async fn full_update(&mut self) -> Result<()> {
-        let pages: u64 = self.pages;
-        self.pages = 2;
-        let site: String = self.net.get_anime_list_html(&mut self.pages, pages).await?;
-        self.anime = self.parser.parse_anime_list(site)?;
+        let pb: ProgressBar = ProgressBar::new(self.pages);
+        pb.set_style(ProgressStyle::with_template("{prefix:.bold.dim} {spinner} {wide_msg}")?
+            .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ "));
+        pb.set_message("Fetching anime...");
+
+        let provider: Arc<dyn Provider> = self.catalogue_provider()?;
+        let mut pages = stream::iter(1..)
+            .map(|page: u64| {
+                let provider: Arc<dyn Provider> = provider.clone();
+                async move { provider.catalogue_page(page).await }
+            })
+            .buffered(CATALOGUE_CONCURRENCY);
+
+        let mut anime: Vec<Anime> = Vec::new();
+        let mut fetched: u64 = 0;
+        while let Some(page) = pages.next().await {
+            let Some(page) = page? else {
+                break;
+            };
+            anime.extend(page);
+
+            fetched += 1;
+            pb.set_position(fetched);
+            match self.pages {
+                0 => pb.set_prefix(format!("[{fetched}/?]")),
+                pages => pb.set_prefix(format!("[{fetched}/{pages}]")),
+            }
+        }
+        self.pages = fetched;
+        pb.finish_with_message("Fetching done!");
+
+        for entry in anime.iter_mut() {
+            if let Some(cached) = self.anime.iter().find(|a| a.url == entry.url) {
+                entry.seasons = cached.seasons.clone();
+                entry.films = cached.films.clone();
+                entry.ovas = cached.ovas.clone();
+                let poster: Option<String> = entry.metadata.poster.take();
+                entry.metadata = cached.metadata.clone();
+                entry.metadata.poster = poster.or(entry.metadata.poster.take());
+                entry.updated_at = cached.updated_at;
+            }
+        }
+        self.anime = anime;
+        self.last_refresh = Some(timestamp());
         self.update()?;
         Ok(())
     }
 
-    pub async fn get_anime_self(&self, anime: Anime) -> Result<Anime> {
-        let anime_html: String = self.net.get_anime_html(anime.url).await?;
-        Ok(self.parser.parse_anime(anime_html)?)
-    }
+    /// Fetches catalogue pages from the first one until a page brings no
+    /// anime that isn't already cached, and adds the new entries in front of
+    /// the cached ones. Falls back to a full update when the cache is empty.
+    /// Returns the number of added anime.
+    pub async fn incremental_update(&mut self) -> Result<usize> {
+        if self.anime.is_empty() {
+            self.full_update().await?;
+            return Ok(self.anime.len());
+        }
 
-    pub fn get_anime_name(&self, name: String) -> Result<Vec<Anime>> {
-        let mut result: Vec<Anime> = Vec::new();
+        let pb: ProgressBar = ProgressBar::new_spinner();
+        pb.set_style(ProgressStyle::with_template("{prefix:.bold.dim} {spinner} {wide_msg}")?);
+        pb.set_message("Fetching new anime...");
+
+        let mut found: Vec<Anime> = Vec::new();
+        let mut page: u64 = 1;
+        let provider: Arc<dyn Provider> = self.catalogue_provider()?;
+        while let Some(anime_page) = provider.catalogue_page(page).await? {
+            pb.set_prefix(format!("[{page}]"));
+
+            let mut new: usize = 0;
+            for anime in anime_page {
+                let known: bool = self.anime.iter().chain(found.iter()).any(|a| a.url == anime.url);
+                if !known {
+                    found.push(anime);
+                    new += 1;
+                }
+            }
+            if new == 0 {
+                break;
+            }
+
+            page += 1;
+        }
 
-        for anime in &self.anime {
-            if anime.name.to_lowercase().contains(name.to_lowercase().as_str()) {
-                result.push(anime.clone());
+        let added: usize = found.len();
+        self.anime.splice(0..0, found);
+        self.last_refresh = Some(timestamp());
+        self.update()?;
+
+        pb.finish_with_message(format!("Fetching done! New anime: {added}"));
+        Ok(added)
+    }
+
+    /// Returns the anime with its episode list and details. With "Update the
+    /// anime that you have already searched for" on, the page is always
+    /// re-parsed. With it off, a cached list younger than `anime_max_age`
+    /// hours is used without a request. Parsed pages are written back to the cache.
+    pub async fn open_anime(&mut self, anime: Anime) -> Result<Anime> {
+        if !self.settings.update_found_anime {
+            let max_age: u64 = self.settings.anime_max_age * 60 * 60;
+            let cached: Option<&Anime> = self.anime
+                .iter()
+                .find(|a| a.url == anime.url && !a.is_empty())
+                .filter(|a| a.updated_at.is_some_and(|t| timestamp().saturating_sub(t) < max_age));
+            if let Some(cached) = cached {
+                return Ok(cached.clone());
             }
         }
 
+        let anime: Anime = self.get_anime_self(anime).await?;
+        match self.anime.iter_mut().find(|a| a.url == anime.url) {
+            Some(cached) => *cached = anime.clone(),
+            None => self.anime.push(anime.clone()),
+        }
+        self.update()?;
+        Ok(anime)
+    }
+
+    pub async fn get_anime_self(&self, anime: Anime) -> Result<Anime> {
+        let mut result: Anime = self.provider_for(&anime)?.anime(&anime).await?;
+        result.url = anime.url;
+        result.base_url = anime.base_url;
+        result.updated_at = Some(timestamp());
+        if result.metadata.poster.is_none() {
+            result.metadata.poster = anime.metadata.poster;
+        }
+        result.name = match anime.name.is_empty() {
+            false => anime.name,
+            true => self.anime
+                .iter()
+                .find(|a| a.url == result.url)
+                .map(|a| a.name.clone())
+                .unwrap_or_else(|| result.slug()),
+        };
         Ok(result)
     }
-    
-    pub async fn download_episode(&self, mut episode: Episode, quality: Quality, pb: &ProgressBar) -> Result<()> {
-        let episode_html: String = self.net.get_episode_html(episode.clone().url).await?;
-        let episode_urls: Episode = self.parser.parse_episode(episode_html)?;
-        episode.quality = episode_urls.quality;
-        self.net.download_episode(episode, quality, pb).await?;
-        Ok(())
+
+    /// Finds anime by name, best matches first.
+    pub fn get_anime_name(&self, name: String) -> Result<Vec<Anime>> {
+        let query: SearchQuery = SearchQuery::parse(name.as_str())?;
+        let mut result: Vec<(f64, &Anime)> = self.anime
+            .iter()
+            .filter_map(|anime| Some((query.score(anime)?, anime)))
+            .collect();
+        result.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        Ok(result.into_iter().map(|(_, anime)| anime.clone()).collect())
     }
     
-    pub async fn download_episodes(&self, episodes: Vec<Episode>, quality: Quality, pbs: &Vec<ProgressBar>) {
-        for i in 0..episodes.len() {
-            self.download_episode(episodes[i].clone(), quality.clone(), &pbs[i]).await
-                .unwrap_or_else(
-                    |e| {
-                        eprintln!("Error during episode download: {e}");
-                    });
+    fn downloader(&self, anime: &Anime) -> Result<Downloader> {
+        Ok(Downloader {
+            net: self.net.clone(),
+            provider: self.provider_for(anime)?,
+            settings: self.settings.clone(),
+            history: self.history.clone(),
+            // Replayed videos are cut to the size of their fixtures, so they never count as downloaded
+            keeps_history: !matches!(self.overrides.transport, TransportMode::Replay(_)),
+        })
+    }
+
+    /// The sources the episode page offers.
+    pub async fn episode_qualities(&self, anime: &Anime, episode: &Episode) -> Result<BTreeSet<Quality>> {
+        Ok(self.provider_for(anime)?.episode(episode).await?.quality)
+    }
+
+    /// Downloads the episodes with `workers` at a time. Meanwhile, bandwidth
+    /// limits saved by another instance (`ando_2 limit` or its settings menu)
+    /// are applied to the running downloads and kept in `settings`.
+    pub async fn download_episodes(
+        &mut self,
+        anime: &Anime,
+        episodes: Vec<Episode>,
+        quality: Quality,
+        fallback: QualityFallback,
+        workers: usize,
+    ) -> Result<usize> {
+        let multi_pb: MultiProgress = MultiProgress::new();
+        let style: ProgressStyle = ProgressStyle::default_bar()
+            .template(
+                "{msg} -> {spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})"
+            )?;
+
+        let total: ProgressBar = multi_pb.add(ProgressBar::new(episodes.len() as u64));
+        total.set_style(ProgressStyle::with_template("Episodes: [{pos}/{len}] [{elapsed_precise}]")?);
+
+        let downloader: Arc<Downloader> = Arc::new(self.downloader(anime)?);
+        let queue: Arc<Mutex<VecDeque<Episode>>> = Arc::new(Mutex::new(episodes.into()));
+        let mut handles: Vec<JoinHandle<usize>> = Vec::new();
+
+        for _ in 0..workers.max(1) {
+            let downloader: Arc<Downloader> = downloader.clone();
+            let anime: Anime = anime.clone();
+            let queue: Arc<Mutex<VecDeque<Episode>>> = queue.clone();
+            let quality: Quality = quality.clone();
+            let multi_pb: MultiProgress = multi_pb.clone();
+            let style: ProgressStyle = style.clone();
+            let total: ProgressBar = total.clone();
+
+            handles.push(tokio::task::spawn(async move {
+                downloader.download_worker(anime, queue, quality, fallback, multi_pb, style, total).await
+            }));
+        }
+
+        let changed_limits: Arc<Mutex<Option<(Rate, Rate)>>> = Arc::default();
+        let watcher: JoinHandle<()> = tokio::task::spawn(watch_limits(
+            self.file().to_path_buf(),
+            self.bandwidth.clone(),
+            (self.settings.limit_rate, self.settings.download_limit_rate),
+            changed_limits.clone(),
+            multi_pb.clone(),
+        ));
+
+        let mut failed: usize = 0;
+        for handle in handles {
+            failed += handle.await?;
         }
+        total.finish();
+        watcher.abort();
+
+        // So that saving the cache later doesn't undo the change
+        let changed_limits: Option<(Rate, Rate)> = *changed_limits.lock().map_err(|_| anyhow!("The bandwidth limits are unavailable"))?;
+        if let Some((total, per_download)) = changed_limits {
+            self.settings.limit_rate = total;
+            self.settings.download_limit_rate = per_download;
+        }
+
+        Ok(failed)
     }
-    
+
     fn folder(&self) -> Result<&Path> {
         self.file().parent().context("Error receiving the cache folder")
     }
@@ -153,3 +491,333 @@ impl Cache {
         Path::new(&self.path)
     }
 }
+
+
+/// What the workers of a download batch share: the network, the provider of
+/// the anime and the history, rather than a copy of the whole cache each.
+struct Downloader {
+    net: Net,
+    provider: Arc<dyn Provider>,
+    settings: Settings,
+    history: Arc<Mutex<History>>,
+    keeps_history: bool,
+}
+
+
+impl Downloader {
+    /// Downloads the source the fallback policy picks for `quality`, logging
+    /// when it isn't the requested resolution. Episodes whose recorded file
+    /// is still complete and of that quality are skipped, which is when this
+    /// returns `false`.
+    async fn download_episode(
+        &self,
+        anime: &Anime,
+        episode: Episode,
+        quality: &Quality,
+        fallback: QualityFallback,
+        multi_pb: &MultiProgress,
+        pb: &ProgressBar,
+    ) -> Result<bool> {
+        let recorded: Option<HistoryEntry> = self.lock_history()?.find(&episode.key()).cloned();
+        let recorded: Option<HistoryEntry> = match recorded {
+            Some(entry) => match entry.check(false)? {
+                FileState::Complete if entry.quality == quality.name() => return Ok(false),
+                FileState::Complete => Some(entry),
+                FileState::Missing => None,
+                FileState::Changed => {
+                    multi_pb.suspend(|| println!(
+                        "{}: {} changed since it was downloaded, downloading again",
+                        episode.name,
+                        entry.path.display(),
+                    ));
+                    None
+                },
+            },
+            None => None,
+        };
+
+        let episode: Episode = self.provider.episode(&episode).await?;
+        let chosen: Quality = match fallback.pick(quality, &episode.quality) {
+            Some(chosen) => chosen.clone(),
+            None => {
+                let offered: Vec<String> = episode.quality.iter().rev().map(|q| q.name()).collect();
+                bail!("{} is not offered (offered: {})", quality.name(), offered.join(", "));
+            },
+        };
+        // The fallback may pick the quality downloaded before
+        if recorded.as_ref().is_some_and(|entry| entry.quality == chosen.name()) {
+            return Ok(false);
+        }
+        if !chosen.equal(quality) {
+            multi_pb.suspend(|| println!(
+                "{}: {} is not offered, downloading {} ({})",
+                episode.name,
+                quality.name(),
+                chosen.name(),
+                fallback.name(),
+            ));
+        }
+
+        let path: PathBuf = self.episode_path(anime, &episode, &chosen)?;
+        if let Some(entry) = recorded {
+            multi_pb.suspend(|| println!(
+                "{}: downloaded in {} before, downloading {}",
+                episode.name,
+                entry.quality,
+                chosen.name(),
+            ));
+            // Otherwise the old file would be continued as if it were a part of the new one
+            if entry.path == path {
+                std::fs::remove_file(&path)?;
+            }
+        }
+        if self.keeps_history {
+            let mut history: MutexGuard<History> = self.lock_history()?;
+            history.start(PendingDownload {
+                anime: anime.name.clone(),
+                anime_url: anime.url.clone(),
+                provider: anime.provider.clone(),
+                episode: episode.clone(),
+                quality: chosen.clone(),
+                path: path.clone(),
+            });
+            history.save()?;
+        }
+        self.net.download_episode(episode.clone(), chosen.clone(), path.as_path(), pb).await?;
+        if self.keeps_history {
+            self.record_download(anime, &episode, &chosen, path).await?;
+        }
+        Ok(true)
+    }
+
+    async fn record_download(&self, anime: &Anime, episode: &Episode, quality: &Quality, path: PathBuf) -> Result<()> {
+        let (size, checksum) = tokio::task::spawn_blocking({
+            let path: PathBuf = path.clone();
+            move || -> Result<(u64, String)> { Ok((std::fs::metadata(&path)?.len(), checksum(&path)?)) }
+        }).await??;
+
+        let mut history: MutexGuard<History> = self.lock_history()?;
+        history.record(HistoryEntry {
+            anime: anime.name.clone(),
+            key: episode.key(),
+            episode: episode.name.clone(),
+            quality: quality.name(),
+            path,
+            size,
+            checksum,
+            downloaded_at: timestamp(),
+        });
+        history.save()
+    }
+
+    fn episode_path(&self, anime: &Anime, episode: &Episode, quality: &Quality) -> Result<PathBuf> {
+        let fields: TemplateFields = TemplateFields {
+            anime: anime.name.clone(),
+            season: episode.season,
+            episode: episode.number.unwrap_or_default(),
+            title: episode.name.clone(),
+            quality: quality.name(),
+        };
+        Ok(Path::new(&self.settings.download_dir).join(self.settings.filename_template.render(&fields)?))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn download_worker(
+        &self,
+        anime: Anime,
+        queue: Arc<Mutex<VecDeque<Episode>>>,
+        quality: Quality,
+        fallback: QualityFallback,
+        multi_pb: MultiProgress,
+        style: ProgressStyle,
+        total: ProgressBar,
+    ) -> usize {
+        let mut failed: usize = 0;
+        loop {
+            let episode: Episode = match queue.lock().map(|mut queue| queue.pop_front()) {
+                Ok(Some(episode)) => episode,
+                _ => break,
+            };
+
+            let pb: ProgressBar = multi_pb.add(ProgressBar::new(0));
+            pb.set_style(style.clone());
+            let result: Result<bool> = self.download_episode(&anime, episode.clone(), &quality, fallback, &multi_pb, &pb).await;
+            multi_pb.remove(&pb);
+            total.inc(1);
+
+            match result {
+                Ok(true) => multi_pb.suspend(|| println!("Downloading complete: {}", episode.name)),
+                Ok(false) => multi_pb.suspend(|| println!("Already downloaded: {}", episode.name)),
+                Err(e) => {
+                    failed += 1;
+                    multi_pb.suspend(|| eprintln!("Error during episode download ({}): {e}", episode.name));
+                },
+            }
+        }
+        failed
+    }
+
+    fn lock_history(&self) -> Result<MutexGuard<'_, History>> {
+        self.history.lock().map_err(|_| anyhow!("The download history is unavailable"))
+    }
+}
+
+
+/// Checks the saved settings every `LIMITS_CHECK_INTERVAL` and applies their
+/// bandwidth limits when they differ from `current`, recording them in `changed`.
+async fn watch_limits(
+    file: PathBuf,
+    bandwidth: Bandwidth,
+    mut current: (Rate, Rate),
+    changed: Arc<Mutex<Option<(Rate, Rate)>>>,
+    multi_pb: MultiProgress,
+) {
+    #[derive(Deserialize)]
+    struct Saved {
+        #[serde(deserialize_with = "settings::deserialize")]
+        settings: Settings,
+    }
+
+    let modified_at = |file: &Path| file.metadata().and_then(|metadata| metadata.modified()).ok();
+    let mut modified: Option<SystemTime> = modified_at(&file);
+    loop {
+        sleep(LIMITS_CHECK_INTERVAL).await;
+        if modified_at(&file) == modified {
+            continue;
+        }
+
+        // A file caught halfway through being written is read again next time
+        let Ok(saved) = tokio::fs::read(&file).await
+            .map_err(anyhow::Error::from)
+            .and_then(|data| Ok(serde_json::from_slice::<Saved>(&data)?))
+        else {
+            continue;
+        };
+        modified = modified_at(&file);
+
+        let limits: (Rate, Rate) = (saved.settings.limit_rate, saved.settings.download_limit_rate);
+        if limits == current {
+            continue;
+        }
+        current = limits;
+        bandwidth.set_limits(limits.0, limits.1);
+        if let Ok(mut changed) = changed.lock() {
+            *changed = Some(limits);
+        }
+        multi_pb.suspend(|| println!(
+            "Bandwidth limit changed to {} in total, {} per download",
+            format_rate(limits.0),
+            format_rate(limits.1),
+        ));
+    }
+}
+
+
+pub fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::mp4;
+    use crate::net::parse_base_url;
+
+    /// Responses recorded with `--record` from a local copy of the site
+    /// served at `BASE_URL`.
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/replay");
+    const BASE_URL: &str = "http://127.0.0.1:8800/";
+
+    /// A cache in its own temporary folder, answered from the fixtures.
+    fn replay_cache(name: &str) -> Cache {
+        let folder: PathBuf = std::env::temp_dir().join(format!("ando_2-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&folder);
+
+        let settings: Settings = Settings {
+            download_dir: folder.join("anime").to_string_lossy().into_owned(),
+            ..Settings::default()
+        };
+
+        let net: Net = Net::default();
+        let mut cache: Cache = Cache::new(
+            net.clone(),
+            provider::all(&net),
+            folder.join("cache.json").display(),
+            settings,
+            0,
+            Vec::new(),
+        );
+        cache.set_overrides(Overrides {
+            base_url: Some(parse_base_url(BASE_URL).unwrap()),
+            transport: TransportMode::Replay(PathBuf::from(FIXTURES)),
+            ..Overrides::default()
+        });
+        cache.apply_settings().unwrap();
+        cache
+    }
+
+    fn naruto() -> Anime {
+        Anime::new("Naruto", format!("{BASE_URL}naruto/"), Vec::new())
+    }
+
+    #[tokio::test]
+    async fn full_update_reads_every_catalogue_page() {
+        let mut cache: Cache = replay_cache("full-update");
+        cache.full_update().await.unwrap();
+
+        let names: Vec<&str> = cache.anime.iter().map(|anime| anime.name.as_str()).collect();
+        assert_eq!(names, ["Naruto", "Bleach", "One Piece"]);
+        assert_eq!(cache.pages, 2);
+        assert_eq!(cache.anime[0].metadata.poster.as_deref(), Some("https://img.example/naruto.jpg"));
+    }
+
+    #[tokio::test]
+    async fn get_anime_self_groups_episodes_and_reads_metadata() {
+        let cache: Cache = replay_cache("get-anime-self");
+        let anime: Anime = cache.get_anime_self(naruto()).await.unwrap();
+
+        let parts: Vec<(String, usize)> = anime.parts()
+            .into_iter()
+            .map(|(name, episodes)| (name, episodes.len()))
+            .collect();
+        assert_eq!(parts, [
+            (String::from("Season 1"), 2),
+            (String::from("Season 2"), 1),
+            (String::from("Films"), 1),
+        ]);
+        assert_eq!(anime.metadata.genres, ["Приключения", "Комедия"]);
+        assert_eq!(anime.metadata.years, [2002, 2007]);
+        assert_eq!(anime.metadata.age_rating.as_deref(), Some("12+"));
+    }
+
+    #[tokio::test]
+    async fn download_episode_writes_the_whole_video() {
+        let cache: Cache = replay_cache("download-episode");
+        let anime: Anime = cache.get_anime_self(naruto()).await.unwrap();
+        let episode: Episode = anime.episodes()[0].clone();
+        let quality: Quality = Quality::new(720);
+
+        let downloader: Downloader = cache.downloader(&anime).unwrap();
+        let downloaded: bool = downloader.download_episode(
+            &anime,
+            episode.clone(),
+            &quality,
+            QualityFallback::Exact,
+            &MultiProgress::with_draw_target(indicatif::ProgressDrawTarget::hidden()),
+            &ProgressBar::hidden(),
+        ).await.unwrap();
+        assert!(downloaded);
+
+        let path: PathBuf = downloader.episode_path(&anime, &episode, &quality).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 40236);
+        assert_eq!(mp4::is_complete(&path).unwrap(), Some(true));
+        assert!(!part_path(&path).exists());
+        // Replayed downloads never reach the history
+        assert!(cache.history().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,291 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+
+use clap::{Parser, Subcommand};
+
+use reqwest::Url;
+
+use crate::anime::{Anime, Episode, Quality, QualityFallback};
+use crate::bandwidth::{format_rate, parse_rate, Rate};
+use crate::cache::{Cache, Overrides};
+use crate::history::{format_timestamp, FileState, HistoryEntry};
+use crate::net::parse_base_url;
+use crate::search::SearchQuery;
+use crate::selection::Selection;
+use crate::transport::TransportMode;
+
+
+#[derive(Parser)]
+#[command(version, about = "Anime download program")]
+pub struct Args {
+    /// Site address to use instead of the one from the settings, e.g. a mirror or a local test server
+    #[arg(long, global = true, value_parser = parse_base_url)]
+    pub base_url: Option<Url>,
+    /// Bandwidth limit shared by all downloads, e.g. 5M or 500K per second (from the settings by default)
+    #[arg(long, global = true, value_name = "RATE", value_parser = parse_rate)]
+    pub limit_rate: Option<Rate>,
+    /// Bandwidth limit for each download on its own (from the settings by default)
+    #[arg(long, global = true, value_name = "RATE", value_parser = parse_rate)]
+    pub download_limit_rate: Option<Rate>,
+    /// Attempts per request before giving up (from the settings by default)
+    #[arg(long, global = true, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub retries: Option<u32>,
+    /// Save every request and response to this directory
+    #[arg(long, global = true, value_name = "DIR", conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+    /// Answer requests from responses saved with `--record` instead of the network
+    #[arg(long, global = true, value_name = "DIR")]
+    pub replay: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+
+impl Args {
+    pub fn overrides(&self) -> Overrides {
+        Overrides {
+            base_url: self.base_url.clone(),
+            limit_rate: self.limit_rate,
+            download_limit_rate: self.download_limit_rate,
+            retries: self.retries,
+            transport: match (&self.record, &self.replay) {
+                (Some(dir), _) => TransportMode::Record(dir.clone()),
+                (_, Some(dir)) => TransportMode::Replay(dir.clone()),
+                _ => TransportMode::Live,
+            },
+        }
+    }
+}
+
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Fetch new anime from the catalogue and save them to the cache
+    UpdateCache {
+        /// Re-fetch the whole catalogue instead of only the new anime
+        #[arg(long)]
+        full: bool,
+    },
+    /// Search the cached catalogue by name, best matches first
+    Search {
+        /// Name to search for; `genre:<name>` and `year:<year>` filter on the details of anime opened before
+        query: String,
+        /// Show the description and the other details known for every result
+        #[arg(short, long)]
+        details: bool,
+    },
+    /// List the episodes of an anime
+    Episodes {
+        anime_url: String,
+    },
+    /// Download episodes of an anime
+    Download {
+        /// Anime page URL or a name that matches exactly one cached anime
+        anime: String,
+        /// Episodes to download, e.g. `1-5,8,10-`, `last 3`, `s2e1-s2e12` or `!7` (all by default)
+        #[arg(short, long)]
+        episodes: Option<String>,
+        /// Video resolution such as 480, 720p or 1080p (the default quality from the settings by default)
+        #[arg(short, long)]
+        quality: Option<String>,
+        /// What to download when an episode lacks the quality: exact, at-or-below, best or nearest (from the settings by default)
+        #[arg(long)]
+        fallback: Option<String>,
+        /// Number of episodes downloaded at the same time (from the settings by default)
+        #[arg(short, long)]
+        jobs: Option<usize>,
+    },
+    /// Change the saved bandwidth limits, which downloads running in another window pick up within seconds
+    Limit {
+        /// Limit shared by all downloads, e.g. 5M or 500K per second, `none` to remove it
+        #[arg(value_parser = parse_rate)]
+        rate: Rate,
+        /// Limit for each download on its own (unchanged by default)
+        #[arg(long, value_name = "RATE", value_parser = parse_rate)]
+        per_download: Option<Rate>,
+    },
+    /// List the downloaded episodes
+    History {
+        /// Only show anime whose name contains this
+        anime: Option<String>,
+        /// Re-read every file and compare it with the recorded checksum
+        #[arg(long)]
+        verify: bool,
+    },
+}
+
+
+impl Command {
+    pub async fn run(self, overrides: Overrides) -> Result<()> {
+        let mut cache: Cache = Cache::default();
+        cache.set_overrides(overrides);
+        cache.load()?;
+
+        let unfinished: usize = cache.unfinished()?.len();
+        if unfinished > 0 {
+            eprintln!("{unfinished} unfinished download(s) found, start without a command to resume or delete them");
+        }
+
+        match self {
+            Self::UpdateCache { full } => Self::update_cache(&mut cache, full).await,
+            Self::Search { query, details } => Self::search(&cache, query, details),
+            Self::Episodes { anime_url } => Self::episodes(&mut cache, anime_url).await,
+            Self::Download { anime, episodes, quality, fallback, jobs } => {
+                Self::download(&mut cache, anime, episodes, quality, fallback, jobs).await
+            },
+            Self::Limit { rate, per_download } => Self::limit(&mut cache, rate, per_download),
+            Self::History { anime, verify } => Self::history(&cache, anime, verify),
+        }
+    }
+
+    async fn update_cache(cache: &mut Cache, full: bool) -> Result<()> {
+        if full {
+            cache.full_update().await?;
+        }
+        else {
+            println!("Added {} anime", cache.incremental_update().await?);
+        }
+        println!("Cached {} anime", cache.anime.len());
+        Ok(())
+    }
+
+    fn search(cache: &Cache, query: String, details: bool) -> Result<()> {
+        let found: Vec<Anime> = cache.get_anime_name(query.clone())?;
+        if found.is_empty() {
+            bail!("Nothing found for \"{query}\"");
+        }
+
+        for anime in found {
+            match anime.metadata.short().as_str() {
+                "" => println!("{} ({})", anime.name, anime.url),
+                short => println!("{} [{short}] ({})", anime.name, anime.url),
+            }
+            if details {
+                println!("{}", anime.metadata.describe());
+            }
+        }
+        Ok(())
+    }
+
+    async fn episodes(cache: &mut Cache, anime_url: String) -> Result<()> {
+        let anime: Anime = cache.open_anime(Anime::new(String::new(), anime_url, Vec::default())).await?;
+        let mut idx: usize = 0;
+        for (part, episodes) in anime.parts() {
+            println!("{part}");
+            for episode in episodes {
+                idx += 1;
+                println!("[{idx}] {} ({})", episode.name, episode.url);
+            }
+        }
+        Ok(())
+    }
+
+    async fn download(
+        cache: &mut Cache,
+        anime: String,
+        episodes: Option<String>,
+        quality: Option<String>,
+        fallback: Option<String>,
+        jobs: Option<usize>,
+    ) -> Result<()> {
+        let anime: Anime = Self::find_anime(cache, anime)?;
+        let anime: Anime = cache.open_anime(anime).await?;
+        let quality: Quality = Quality::from_name(quality.unwrap_or(cache.settings.default_quality.clone()).as_str())?;
+        let fallback: QualityFallback = match fallback {
+            Some(fallback) => QualityFallback::from_name(fallback.as_str())?,
+            None => cache.settings.quality_fallback,
+        };
+        let jobs: usize = jobs.unwrap_or(cache.settings.default_concurrency);
+        let episodes: Vec<Episode> = match episodes {
+            Some(episodes) => Selection::parse(episodes.as_str())?.resolve(&anime.episodes())?,
+            None => anime.episodes(),
+        };
+
+        let failed: usize = cache.download_episodes(&anime, episodes, quality, fallback, jobs).await?;
+        if failed > 0 {
+            bail!("{failed} episode(s) failed to download");
+        }
+        Ok(())
+    }
+
+    fn limit(cache: &mut Cache, rate: Rate, per_download: Option<Rate>) -> Result<()> {
+        cache.settings.limit_rate = rate;
+        if let Some(per_download) = per_download {
+            cache.settings.download_limit_rate = per_download;
+        }
+        cache.update()?;
+        println!(
+            "Bandwidth limit: {} in total, {} per download",
+            format_rate(cache.settings.limit_rate),
+            format_rate(cache.settings.download_limit_rate),
+        );
+        Ok(())
+    }
+
+    fn history(cache: &Cache, anime: Option<String>, verify: bool) -> Result<()> {
+        let entries: Vec<HistoryEntry> = cache.history()?;
+        if entries.is_empty() {
+            println!("Nothing has been downloaded yet");
+            return Ok(());
+        }
+
+        let filter: String = anime.clone().unwrap_or_default().to_lowercase();
+        let entries: Vec<HistoryEntry> = entries
+            .into_iter()
+            .filter(|entry| entry.anime.to_lowercase().contains(filter.as_str()))
+            .collect();
+        if entries.is_empty() {
+            println!("Nothing downloaded matches \"{}\"", anime.unwrap_or_default());
+            return Ok(());
+        }
+
+        let mut broken: usize = 0;
+        for entry in entries {
+            println!(
+                "{}  {} {}  {} [{}, {:.1} MiB] {}",
+                format_timestamp(entry.downloaded_at),
+                entry.anime,
+                entry.key.label(),
+                entry.episode,
+                entry.quality,
+                entry.size as f64 / (1024.0 * 1024.0),
+                entry.path.display(),
+            );
+            if verify {
+                match entry.check(true)? {
+                    FileState::Complete => println!("    OK"),
+                    FileState::Missing => {
+                        broken += 1;
+                        println!("    MISSING");
+                    },
+                    FileState::Changed => {
+                        broken += 1;
+                        println!("    CHANGED");
+                    },
+                }
+            }
+        }
+        if broken > 0 {
+            bail!("{broken} downloaded file(s) are missing or changed");
+        }
+        Ok(())
+    }
+
+    fn find_anime(cache: &Cache, anime: String) -> Result<Anime> {
+        if anime.starts_with("http://") || anime.starts_with("https://") {
+            return Ok(Anime::new(String::new(), anime, Vec::default()));
+        }
+
+        let found: Vec<Anime> = cache.get_anime_name(anime.clone())?;
+        let query: SearchQuery = SearchQuery::parse(anime.as_str())?;
+        if let Some(exact) = found.iter().find(|a| query.is_exact(a)) {
+            return Ok(exact.clone());
+        }
+        match found.len() {
+            0 => bail!("Nothing found for \"{anime}\" (try `update-cache` first)"),
+            1 => Ok(found[0].clone()),
+            n => bail!("\"{anime}\" matches {n} anime, use a more precise name or the anime URL"),
+        }
+    }
+}
@@ -1,19 +1,11 @@
-use std::{
-    io::{stdin, stdout, Write},
-    collections::HashSet,
-};
+use std::io::{stdout, Write};
 
-use anyhow::{bail, Context, Result};
-
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-
-use rayon::prelude::*;
-
-use tokio::task::JoinHandle;
+use anyhow::{Context, Result};
 
 use crate::anime::{Anime, Episode, Quality};
 use crate::cache::Cache;
 use crate::cli::states::State;
+use crate::selection::Selection;
 
 
 pub struct DownloadMenu {
@@ -21,8 +13,10 @@ pub struct DownloadMenu {
     download_state: DownloadState,
     selected_anime_list: Vec<Anime>,
     selected_anime: Anime,
+    season_episodes: Vec<Episode>,
     download_type: DownloadType,
-    selected_episodes: HashSet<Episode>,
+    selected_episodes: Vec<Episode>,
+    offered_qualities: Vec<Quality>,
     selected_quality: Quality,
     thread_count: usize,
 }
@@ -35,8 +29,10 @@ impl Default for DownloadMenu {
             DownloadState::default(),
             Vec::default(),
             Anime::default(),
+            Vec::default(),
             DownloadType::default(),
-            HashSet::default(),
+            Vec::default(),
+            Vec::default(),
             Quality::default(),
             1
         )
@@ -45,13 +41,16 @@ impl Default for DownloadMenu {
 
 
 impl DownloadMenu {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         menu: String,
         download_state: DownloadState,
         selected_anime_list: Vec<Anime>,
         selected_anime: Anime,
+        season_episodes: Vec<Episode>,
         download_type: DownloadType,
-        selected_episodes: HashSet<Episode>,
+        selected_episodes: Vec<Episode>,
+        offered_qualities: Vec<Quality>,
         selected_quality: Quality,
         thread_count: usize,
     ) -> Self {
@@ -60,8 +59,10 @@ impl DownloadMenu {
             download_state,
             selected_anime_list,
             selected_anime,
+            season_episodes,
             download_type,
             selected_episodes,
+            offered_qualities,
             selected_quality,
             thread_count,
         }
@@ -76,13 +77,19 @@ impl DownloadMenu {
     
     pub async fn process_action(&mut self, action: String, cache: &mut Cache) -> Result<State> {
         match self.download_state {
-            DownloadState::SelectAnime => self.select_anime(action, &cache).await?,
+            DownloadState::SelectAnime => self.select_anime(action, cache).await?,
+            DownloadState::SelectSeason => self.select_season(action)?,
             DownloadState::SelectDownloadType => self.select_download_type(action)?,
-            DownloadState::SelectEpisode => self.select_episode(action)?,
-            DownloadState::SelectQuality => self.select_quality(action)?,
-            DownloadState::SelectThreadCount => self.select_thread_count(action)?,
+            DownloadState::SelectEpisode => {
+                self.select_episode(action)?;
+                if matches!(self.download_state, DownloadState::SelectQuality) {
+                    self.load_qualities(cache).await;
+                }
+            },
+            DownloadState::SelectQuality => self.select_quality(action, cache)?,
+            DownloadState::SelectThreadCount => self.select_thread_count(action, cache)?,
             DownloadState::Download => {
-                self.start_downloading(action, &cache).await?;
+                self.start_downloading(action, cache).await?;
                 self.selected_anime_list = cache.anime.clone();
                 return Ok(State::MainMenu);
             },
@@ -93,10 +100,11 @@ impl DownloadMenu {
     fn generate_menu(&mut self, cache: &Cache) {
         match self.download_state {
             DownloadState::SelectAnime => self.generate_select_anime_list_menu(cache),
+            DownloadState::SelectSeason => self.generate_select_season_menu(),
             DownloadState::SelectDownloadType => self.generate_select_download_type_menu(),
             DownloadState::SelectEpisode => self.generate_select_episode_menu(),
-            DownloadState::SelectQuality => self.generate_select_quality_menu(),
-            DownloadState::SelectThreadCount => self.generate_select_thread_count_menu(),
+            DownloadState::SelectQuality => self.generate_select_quality_menu(cache),
+            DownloadState::SelectThreadCount => self.generate_select_thread_count_menu(cache),
             DownloadState::Download => self.generate_download_menu(),
         }
     }
@@ -109,13 +117,34 @@ impl DownloadMenu {
         }
 
         for (idx, anime) in self.selected_anime_list.iter().enumerate() {
-            menu += format!("[{}] -> {} ({})\n", idx + 1, anime.name, anime.url).as_str();
+            menu += match anime.metadata.short().as_str() {
+                "" => format!("[{}] -> {} ({})\n", idx + 1, anime.name, anime.url),
+                short => format!("[{}] -> {} [{short}] ({})\n", idx + 1, anime.name, anime.url),
+            }.as_str();
         }
+        menu += "Type a name to search, `genre:<name>` and `year:<year>` narrow it down\n";
         self.menu = menu + "~$ ";
     }
     
+    fn generate_anime_details(&self) -> String {
+        format!("{}\n{}\n", self.selected_anime.name, self.selected_anime.metadata.describe())
+    }
+
+    fn generate_select_season_menu(&mut self) {
+        let mut menu: String = self.generate_anime_details();
+        let parts: Vec<(String, Vec<Episode>)> = self.selected_anime.parts();
+        for (idx, (part, episodes)) in parts.iter().enumerate() {
+            menu += format!("[{}] -> {part} ({} episodes)\n", idx + 1, episodes.len()).as_str();
+        }
+        menu += format!("[{}] -> Everything\n", parts.len() + 1).as_str();
+        self.menu = menu + "~$ ";
+    }
+
     fn generate_select_download_type_menu(&mut self) {
         let mut menu: String = String::new();
+        if self.selected_anime.parts().len() < 2 {
+            menu += self.generate_anime_details().as_str();
+        }
         for (idx, t) in DownloadType::arr().iter().enumerate() {
             menu += format!("[{}] -> {}\n", idx + 1, t.val()).as_str();
         }
@@ -126,14 +155,14 @@ impl DownloadMenu {
         match self.download_type {
             DownloadType::OneEpisode(_) => self.generate_select_one_episode_menu(),
             DownloadType::SomeEpisodes(_) => self.generate_select_some_episodes_menu(),
-            DownloadType::RangeEpisodes(_) => self.generate_select_range_episodes_menu(),
+            DownloadType::SelectionEpisodes(_) => self.generate_select_episodes_by_expression_menu(),
             DownloadType::AllEpisodes(_) => self.generate_select_all_episodes_menu(),
         }
     }
     
     fn generate_select_one_episode_menu(&mut self) {
         let mut menu: String = String::new();
-        for (idx, episode) in self.selected_anime.episodes.iter().enumerate() {
+        for (idx, episode) in self.season_episodes.iter().enumerate() {
             menu += format!("[{}] -> {}\n", idx + 1, episode.name).as_str();
         }
         self.menu = menu + "~$ ";
@@ -141,7 +170,7 @@ impl DownloadMenu {
 
     fn generate_select_some_episodes_menu(&mut self) {
         let mut menu: String = String::new();
-        for (idx, episode) in self.selected_anime.episodes.iter().enumerate() {
+        for (idx, episode) in self.season_episodes.iter().enumerate() {
             let star: String = if self.selected_episodes.contains(episode) {
                 String::from("*")
             }
@@ -150,29 +179,37 @@ impl DownloadMenu {
             };
             menu += format!("[{}] [{}] -> {}\n", idx + 1, star, episode.name).as_str();
         }
-        menu += format!("[{}] -> Done\n", self.selected_anime.episodes.len() + 1).as_str();
+        menu += format!("[{}] -> Done\n", self.season_episodes.len() + 1).as_str();
         self.menu = menu + "~$ ";
     }
     
-    fn generate_select_range_episodes_menu(&mut self) {
-        self.menu = String::from("Select the episode including which range will start: ");
+    fn generate_select_episodes_by_expression_menu(&mut self) {
+        let mut menu: String = String::new();
+        for (idx, episode) in self.season_episodes.iter().enumerate() {
+            menu += format!("[{}] -> {}\n", idx + 1, episode.name).as_str();
+        }
+        menu += "Select episodes, e.g. `1-5,8,10-`, `last 3`, `s2e1-s2e12`, `!7`\n";
+        self.menu = menu + "~$ ";
     }
 
     fn generate_select_all_episodes_menu(&mut self) {
         self.menu = String::from("Selecting all episodes...\n");
     }
 
-    fn generate_select_quality_menu(&mut self) {
+    fn generate_select_quality_menu(&mut self, cache: &Cache) {
         let mut menu: String = String::new();
-        for (idx, quality) in Quality::arr().iter().enumerate() {
-            menu += format!("[{}] -> {}\n", idx + 1, quality.val()).as_str();
+        for (idx, quality) in self.offered_qualities.iter().enumerate() {
+            menu += format!("[{}] -> {}\n", idx + 1, quality.describe()).as_str();
         }
+        menu += "Or type a resolution, e.g. 1080p\n";
+        menu += format!("Press Enter for the default quality ({})\n", cache.settings.default_quality).as_str();
         self.menu = menu + "~$ ";
     }
 
-    fn generate_select_thread_count_menu(&mut self) {
-        self.menu = String::from(
-            "Select the number of episodes that will be downloaded at the same time\n"
+    fn generate_select_thread_count_menu(&mut self, cache: &Cache) {
+        self.menu = format!(
+            "Select the number of episodes that will be downloaded at the same time [{}]\n",
+            cache.settings.default_concurrency,
         );
         self.menu += "~$ "
     }
@@ -181,18 +218,22 @@ impl DownloadMenu {
         let mut menu: String = String::new();
 
         menu += format!("Selected anime: {}\n", self.selected_anime.name).as_str();
-        menu += format!("Selected episodes: {:#?}\n", self.selected_episodes.par_iter().map(|e| e.name.clone()).collect::<Vec<String>>()).as_str();
-        menu += format!("Selected quality: {}\n", self.selected_quality.val()).as_str();
+        menu += format!("Selected episodes: {:#?}\n", self.selected_episodes.iter().map(|e| e.name.clone()).collect::<Vec<String>>()).as_str();
+        menu += format!("Selected quality: {}\n", self.selected_quality.describe()).as_str();
         menu += format!("Selected thread count: {}\n", self.thread_count).as_str();
 
         self.menu = menu + "Start download? [Y/n]: ";
     }
 
-    async fn select_anime(&mut self, action: String, cache: &Cache) -> Result<()> {
+    async fn select_anime(&mut self, action: String, cache: &mut Cache) -> Result<()> {
         match self.parse_action(action.clone()) {
             Ok(index) => {
                 self.selected_anime = self.get_anime(index, cache).await?;
-                self.download_state = DownloadState::SelectDownloadType;
+                self.season_episodes = self.selected_anime.episodes();
+                self.download_state = match self.selected_anime.parts().len() {
+                    0 | 1 => DownloadState::SelectDownloadType,
+                    _ => DownloadState::SelectSeason,
+                };
             },
             Err(_) => {
                 self.selected_anime_list = cache.get_anime_name(action)?;
@@ -202,14 +243,30 @@ impl DownloadMenu {
         Ok(())
     }
 
-    async fn get_anime(&self, id: usize, cache: &Cache) -> Result<Anime> {
+    async fn get_anime(&self, id: usize, cache: &mut Cache) -> Result<Anime> {
         let anime: Anime = self.selected_anime_list
             .get(id)
             .context("Error when trying to select an anime")?
             .clone();
-        Ok(cache.get_anime_self(anime).await?)
+        cache.open_anime(anime).await
     }
     
+    fn select_season(&mut self, action: String) -> Result<()> {
+        let index: usize = self.parse_action(action)?;
+        let parts: Vec<(String, Vec<Episode>)> = self.selected_anime.parts();
+
+        self.season_episodes = match index == parts.len() {
+            true => self.selected_anime.episodes(),
+            false => parts
+                .into_iter()
+                .nth(index)
+                .context("Error when selecting a season")?
+                .1,
+        };
+        self.download_state = DownloadState::SelectDownloadType;
+        Ok(())
+    }
+
     fn select_download_type(&mut self, action: String) -> Result<()> {
         let index: usize = self.parse_action(action)?;
         self.download_type = DownloadType::arr()
@@ -224,7 +281,7 @@ impl DownloadMenu {
         match self.download_type {
             DownloadType::OneEpisode(_) => self.select_one_episode(action)?,
             DownloadType::SomeEpisodes(_) => self.select_some_episodes(action)?,
-            DownloadType::RangeEpisodes(_) => self.select_range_episodes(action)?,
+            DownloadType::SelectionEpisodes(_) => self.select_episodes_by_expression(action)?,
             DownloadType::AllEpisodes(_) => self.select_all_episodes(),
         }
         Ok(())
@@ -232,8 +289,8 @@ impl DownloadMenu {
     
     fn select_one_episode(&mut self, action: String) -> Result<()> {
         let index: usize = self.parse_action(action)?;
-        self.selected_episodes = HashSet::from([
-            self.selected_anime.episodes
+        self.selected_episodes = Vec::from([
+            self.season_episodes
                 .get(index)
                 .context("Error when selecting an episode")?
                 .clone()
@@ -247,47 +304,34 @@ impl DownloadMenu {
     fn select_some_episodes(&mut self, action: String) -> Result<()> {
         let index: usize = self.parse_action(action)?;
 
-        if index == self.selected_anime.episodes.len() {
+        if index == self.season_episodes.len() {
             self.download_state = DownloadState::SelectQuality;
             return Ok(());
         }
         
-        let episode: Episode = self.selected_anime.episodes
+        let episode: Episode = self.season_episodes
             .get(index)
             .context("Error when selecting an episode")?
             .clone();
         
         if self.selected_episodes.contains(&episode) {
-            self.selected_episodes.remove(&episode);
+            self.selected_episodes.retain(|e| *e != episode);
         }
         else {
-            self.selected_episodes.insert(episode);
+            // Kept in the order of the episode list, not the order of clicks
+            self.selected_episodes = self.season_episodes
+                .iter()
+                .filter(|e| **e == episode || self.selected_episodes.contains(e))
+                .cloned()
+                .collect();
         }
         
         Ok(())
     }
     
-    fn select_range_episodes(&mut self, action: String) -> Result<()> {
-        let start_range: usize = self.parse_action(action.clone())?;
-        
-        if start_range >= self.selected_anime.episodes.len() {
-            bail!("Error when entering the initial number of the range");
-        }
-        
-        let mut end_range: String = String::new();
-        print!(
-            "Select the episode including which the range will end [{}..{}]: ",
-            start_range + 2,
-            self.selected_anime.episodes.len()
-        );
-        stdout().flush()?;
-        stdin().read_line(&mut end_range)?;
-        let end_range: usize = self.parse_action(end_range)?;
-        
-        self.selected_episodes = self.selected_anime.episodes
-            .get(start_range..=end_range)
-            .context("Error when selecting a range of episodes")?
-            .par_iter().map(|e| e.clone()).collect();
+    fn select_episodes_by_expression(&mut self, action: String) -> Result<()> {
+        self.selected_episodes = Selection::parse(action.as_str())?
+            .resolve(&self.season_episodes)?;
         
         self.download_state = DownloadState::SelectQuality;
         
@@ -295,20 +339,17 @@ impl DownloadMenu {
     }
     
     fn select_all_episodes(&mut self) {
-        self.selected_episodes = self.selected_anime.episodes
-            .par_iter()
-            .map(|e| e.clone())
-            .collect();
+        self.selected_episodes = self.season_episodes.clone();
         
         self.download_state = DownloadState::SelectQuality;
     }
     
-    fn select_quality(&mut self, action: String) -> Result<()> {
-        let index: usize = self.parse_action(action)?;
-        self.selected_quality = Quality::arr()
-            .get(index)
-            .context("Error during quality selection")?
-            .clone();
+    fn select_quality(&mut self, action: String, cache: &Cache) -> Result<()> {
+        self.selected_quality = match action.parse::<usize>() {
+            _ if action.is_empty() => Quality::from_name(cache.settings.default_quality.as_str())?,
+            Ok(index) if (1..=self.offered_qualities.len()).contains(&index) => self.offered_qualities[index - 1].clone(),
+            _ => Quality::from_name(action.as_str())?,
+        };
 
         self.download_state = DownloadState::SelectThreadCount;
 
@@ -320,8 +361,26 @@ impl DownloadMenu {
         Ok(())
     }
     
-    fn select_thread_count(&mut self, action: String) -> Result<()> {
-        self.thread_count = action.parse()?;
+    /// Lists the sources of the first selected episode. The others are
+    /// expected to have the same ones, so only one page is requested.
+    async fn load_qualities(&mut self, cache: &Cache) {
+        let Some(episode) = self.selected_episodes.first() else {
+            return;
+        };
+        self.offered_qualities = match cache.episode_qualities(&self.selected_anime, episode).await {
+            Ok(qualities) => qualities.into_iter().rev().collect(),
+            Err(e) => {
+                eprintln!("Could not load the qualities of {}: {e}", episode.name);
+                Vec::new()
+            },
+        };
+    }
+
+    fn select_thread_count(&mut self, action: String, cache: &Cache) -> Result<()> {
+        self.thread_count = match action.is_empty() {
+            true => cache.settings.default_concurrency,
+            false => action.parse()?,
+        };
 
         if self.thread_count < 1 {
             self.thread_count = 1;
@@ -337,51 +396,23 @@ impl DownloadMenu {
         Ok(())
     }
     
-    async fn start_downloading(&self, action: String, cache: &Cache) -> Result<()> {
-        // TODO: Rewrite to queue and threads, that take episodes from this queue
+    async fn start_downloading(&self, action: String, cache: &mut Cache) -> Result<()> {
         if (action.to_lowercase() != "y") && (action.to_lowercase() != "yes") {
             println!("Download canceled");
             return Ok(());
         }
         
-        let multi_pb: MultiProgress = MultiProgress::new();
-        
-        let episodes: Vec<Episode> = self.selected_episodes.par_iter().map(|e| e.clone()).collect();
-        let chunks: Vec<&[Episode]> = episodes.par_chunks(episodes.len().div_ceil(self.thread_count)).collect();
-        let mut handles: Vec<JoinHandle<()>> = Vec::new();
-        
-        for chunk in chunks {
-            let mut pbs: Vec<ProgressBar> = Vec::new();
-            for pb in chunk
-                .par_iter()
-                .map(|_| -> Result<ProgressBar> {
-                    let pb: ProgressBar = multi_pb.add(ProgressBar::new(0));
-                    pb.set_style(
-                        ProgressStyle::default_bar()
-                            .template(
-                                "{msg} -> {spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})"
-                            )?
-                    );
-                    Ok(pb)
-                }).collect::<Vec<Result<ProgressBar>>>() {
-                pbs.push(pb?);
-            }
-            
-            let cache: Cache = cache.clone();
-            let eps: Vec<Episode> = chunk.to_vec();
-            let quality: Quality = self.selected_quality.clone();
-            
-            let handle: JoinHandle<()> = tokio::task::spawn(async move {
-                cache.download_episodes(eps, quality, &pbs).await;
-            });
-            
-            handles.push(handle);
+        let episodes: Vec<Episode> = self.selected_episodes.clone();
+        let failed: usize = cache.download_episodes(
+            &self.selected_anime,
+            episodes,
+            self.selected_quality.clone(),
+            cache.settings.quality_fallback,
+            self.thread_count,
+        ).await?;
+        if failed > 0 {
+            eprintln!("{failed} episode(s) failed to download");
         }
-
-        for handle in handles {
-            handle.await?;
-        }
-        
         Ok(())
     }
     
@@ -393,8 +424,11 @@ impl DownloadMenu {
 }
 
 
+#[derive(Default)]
 enum DownloadState {
+    #[default]
     SelectAnime,
+    SelectSeason,
     SelectDownloadType,
     SelectEpisode,
     SelectQuality,
@@ -403,18 +437,11 @@ enum DownloadState {
 }
 
 
-impl Default for DownloadState {
-    fn default() -> Self {
-        Self::SelectAnime
-    }
-}
-
-
 #[derive(Clone, PartialEq)]
 enum DownloadType {
     OneEpisode(String),
     SomeEpisodes(String),
-    RangeEpisodes(String),
+    SelectionEpisodes(String),
     AllEpisodes(String),
 }
 
@@ -431,7 +458,7 @@ impl DownloadType {
         [
             Self::OneEpisode(String::from("Download one episode")),
             Self::SomeEpisodes(String::from("Download some episodes")),
-            Self::RangeEpisodes(String::from("Download range episodes")),
+            Self::SelectionEpisodes(String::from("Download episodes by selection (e.g. 1-5,8,10-)")),
             Self::AllEpisodes(String::from("Download all episodes")),
         ]
     }
@@ -444,7 +471,7 @@ impl DownloadType {
         match self {
             Self::OneEpisode(_) => Self::OneEpisode(String::new()),
             Self::SomeEpisodes(_) => Self::SomeEpisodes(String::new()),
-            Self::RangeEpisodes(_) => Self::RangeEpisodes(String::new()),
+            Self::SelectionEpisodes(_) => Self::SelectionEpisodes(String::new()),
             Self::AllEpisodes(_) => Self::AllEpisodes(String::new()),
         }
     }
@@ -453,7 +480,7 @@ impl DownloadType {
         match self {
             Self::OneEpisode(text) => text,
             Self::SomeEpisodes(text) => text,
-            Self::RangeEpisodes(text) => text,
+            Self::SelectionEpisodes(text) => text,
             Self::AllEpisodes(text) => text,
         }.clone()
     }
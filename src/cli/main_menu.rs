@@ -47,11 +47,17 @@ impl MainMenu {
             Action::DownloadAnime(_) => State::DownloadMenu,
             Action::Settings(_) => State::SettingsMenu,
             Action::UpdateCache(_) => Self::update_cache(cache).await?,
+            Action::RebuildCache(_) => Self::rebuild_cache(cache).await?,
             Action::Exit(_) => exit(0),
         })
     }
     
     pub async fn update_cache(cache: &mut Cache) -> Result<State> {
+        cache.incremental_update().await?;
+        Ok(State::MainMenu)
+    }
+
+    pub async fn rebuild_cache(cache: &mut Cache) -> Result<State> {
         cache.full_update().await?;
         Ok(State::MainMenu)
     }
@@ -62,16 +68,18 @@ enum Action {
     DownloadAnime(String),
     Settings(String),
     UpdateCache(String),
+    RebuildCache(String),
     Exit(String),
 }
 
 
 impl Action {
-    fn arr() -> [Action; 4] {
+    fn arr() -> [Action; 5] {
         [
             Action::DownloadAnime(String::from("Download anime")),
             Action::Settings(String::from("Settings")),
             Action::UpdateCache(String::from("Update cache")),
+            Action::RebuildCache(String::from("Rebuild cache")),
             Action::Exit(String::from("Exit")),
         ]
     }
@@ -81,6 +89,7 @@ impl Action {
             Self::DownloadAnime(text) => text,
             Self::Settings(text) => text,
             Self::UpdateCache(text) => text,
+            Self::RebuildCache(text) => text,
             Self::Exit(text) => text,
         }.clone()
     }
@@ -2,24 +2,31 @@ mod states;
 mod main_menu;
 mod settings_menu;
 mod download_menu;
+mod unfinished_menu;
+mod commands;
 
 
 use std::io::stdin;
 
 use anyhow::Result;
 
-use crate::cache::Cache;
+use crate::cache::{Cache, Overrides};
 use crate::cli::download_menu::DownloadMenu;
 use crate::cli::main_menu::MainMenu;
 use crate::cli::settings_menu::SettingsMenu;
 use crate::cli::states::State;
+use crate::cli::unfinished_menu::UnfinishedMenu;
 
+pub use crate::cli::commands::Args;
+
+#[allow(clippy::upper_case_acronyms)]
 pub struct CLI {
     cache: Cache,
     state: State,
     main_menu: MainMenu,
     settings_menu: SettingsMenu,
     download_menu: DownloadMenu,
+    unfinished_menu: UnfinishedMenu,
 }
 
 
@@ -31,24 +38,42 @@ impl Default for CLI {
             MainMenu::default(),
             SettingsMenu::default(),
             DownloadMenu::default(),
+            UnfinishedMenu::default(),
         )
     }
 }
 
 
 impl CLI {
-    pub fn new(cache: Cache, state: State, main_menu: MainMenu, settings_menu: SettingsMenu, download_menu: DownloadMenu) -> Self {
+    pub fn new(
+        cache: Cache,
+        state: State,
+        main_menu: MainMenu,
+        settings_menu: SettingsMenu,
+        download_menu: DownloadMenu,
+        unfinished_menu: UnfinishedMenu,
+    ) -> Self {
         Self {
             cache,
             state,
             main_menu,
             settings_menu,
             download_menu,
+            unfinished_menu,
         }
     }
 
-    pub async fn start(&mut self) -> Result<()> {
+    pub fn set_overrides(&mut self, overrides: Overrides) {
+        self.cache.set_overrides(overrides);
+    }
+
+    /// Runs the menus. With `check_unfinished`, leftover `.part` files are
+    /// offered for resume or cleanup first.
+    pub async fn start(&mut self, check_unfinished: bool) -> Result<()> {
         self.cache.load()?;
+        if check_unfinished && !self.cache.unfinished()?.is_empty() {
+            self.state = State::UnfinishedMenu;
+        }
         loop {
             self.show_actions()?;
             self.process_action().await?;
@@ -60,6 +85,7 @@ impl CLI {
             State::MainMenu => self.main_menu.show_actions(),
             State::SettingsMenu => self.settings_menu.show_actions(&self.cache.settings),
             State::DownloadMenu => self.download_menu.show_actions(&self.cache),
+            State::UnfinishedMenu => self.unfinished_menu.show_actions(&self.cache),
         }
     }
 
@@ -72,6 +98,7 @@ impl CLI {
             State::MainMenu => self.main_menu.process_action(action, &mut self.cache).await?,
             State::SettingsMenu => self.settings_menu.process_action(action, &mut self.cache)?,
             State::DownloadMenu => self.download_menu.process_action(action, &mut self.cache).await?,
+            State::UnfinishedMenu => self.unfinished_menu.process_action(action, &mut self.cache).await?,
         };
         
         Ok(())
@@ -4,62 +4,125 @@ use anyhow::{Context, Result};
 
 use crate::cache::Cache;
 use crate::cli::states::State;
-use crate::settings::{Options, Settings};
+use crate::settings::{OptionKind, Options, Settings};
 
 
 pub struct SettingsMenu {
     menu: String,
+    editing: Option<usize>,
 }
 
 
 impl Default for SettingsMenu {
     fn default() -> Self {
-        Self::new(String::default())
+        Self::new(String::default(), None)
     }
 }
 
 
 impl SettingsMenu {
-    pub fn new(menu: String) -> Self {
+    pub fn new(menu: String, editing: Option<usize>) -> Self {
         Self {
             menu,
+            editing,
         }
     }
-    
+
     pub fn show_actions(&mut self, settings: &Settings) -> Result<()> {
-        self.generate_menu(settings);
+        self.generate_menu(settings)?;
         print!("{}", self.menu);
         stdout().flush()?;
         Ok(())
     }
-    
-    pub fn process_action(&self, action: String, cache: &mut Cache) -> Result<State> {
+
+    pub fn process_action(&mut self, action: String, cache: &mut Cache) -> Result<State> {
+        if let Some(index) = self.editing.take() {
+            return self.edit_option(index, action, cache);
+        }
+
         let index: usize = action.parse::<usize>()?
             .checked_sub(1)
             .context("Error during user input conversion")?;
-        
+
         if index == Options::arr().len() {
             return Ok(State::MainMenu);
         }
-        
-        let option: String = Options::arr()
-            .get(index)
-            .context("Error while using user input")?
-            .val();
-        
-        cache.settings.change_option(option)?;
+
+        let option: Options = Options::arr()
+            .into_iter()
+            .nth(index)
+            .context("Error while using user input")?;
+
+        match option.kind() {
+            OptionKind::Toggle => {
+                cache.settings.toggle(&option)?;
+                cache.apply_settings()?;
+                cache.update()?;
+            },
+            _ => self.editing = Some(index),
+        }
+
+        Ok(State::SettingsMenu)
+    }
+
+    fn edit_option(&mut self, index: usize, action: String, cache: &mut Cache) -> Result<State> {
+        let option: Options = Options::arr()
+            .into_iter()
+            .nth(index)
+            .context("Error while using user input")?;
+
+        let value: String = match option.kind() {
+            OptionKind::Choice(choices) => action.parse::<usize>()?
+                .checked_sub(1)
+                .and_then(|idx| choices.get(idx))
+                .context("Error while using user input")?
+                .clone(),
+            _ => action,
+        };
+
+        cache.settings.set(&option, value.as_str())?;
+        cache.apply_settings()?;
         cache.update()?;
-        
+
         Ok(State::SettingsMenu)
     }
-    
-    fn generate_menu(&mut self, settings: &Settings) {
+
+    fn generate_menu(&mut self, settings: &Settings) -> Result<()> {
+        if let Some(index) = self.editing {
+            return self.generate_edit_menu(index, settings);
+        }
+
         let mut menu: String = String::new();
-        for (idx, (setting, state)) in settings.settings.iter().enumerate() {
-            let star: String = if *state { String::from("*") } else { String::from(" ") };
-            menu += format!("[{}] [{star}] -> {setting}\n", idx + 1).as_str();
+        for (idx, option) in Options::arr().iter().enumerate() {
+            menu += match option.kind() {
+                OptionKind::Toggle => {
+                    let star: String = if settings.get(option) == "true" { String::from("*") } else { String::from(" ") };
+                    format!("[{}] [{star}] -> {}\n", idx + 1, option.val())
+                },
+                _ => format!("[{}] -> {}: {}\n", idx + 1, option.val(), settings.get(option)),
+            }.as_str();
         }
         menu += format!("[{}] -> Back\n", Options::arr().len() + 1).as_str();
         self.menu = menu + "~$ ";
+        Ok(())
+    }
+
+    fn generate_edit_menu(&mut self, index: usize, settings: &Settings) -> Result<()> {
+        let option: Options = Options::arr()
+            .into_iter()
+            .nth(index)
+            .context("Error while using user input")?;
+
+        let mut menu: String = format!("{} (current: {})\n", option.val(), settings.get(&option));
+        if !option.hint().is_empty() {
+            menu += format!("{}\n", option.hint()).as_str();
+        }
+        if let OptionKind::Choice(choices) = option.kind() {
+            for (idx, choice) in choices.iter().enumerate() {
+                menu += format!("[{}] -> {choice}\n", idx + 1).as_str();
+            }
+        }
+        self.menu = menu + "~$ ";
+        Ok(())
     }
 }
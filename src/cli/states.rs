@@ -1,12 +1,9 @@
+#[derive(Default)]
+#[allow(clippy::enum_variant_names)]
 pub enum State {
+    #[default]
     MainMenu,
     DownloadMenu,
     SettingsMenu,
-}
-
-
-impl Default for State {
-    fn default() -> Self {
-        Self::MainMenu
-    }
+    UnfinishedMenu,
 }
@@ -0,0 +1,95 @@
+use std::io::{stdout, Write};
+
+use anyhow::{Context, Result};
+
+use crate::cache::Cache;
+use crate::cli::states::State;
+use crate::history::Unfinished;
+
+
+pub struct UnfinishedMenu {
+    menu: String,
+}
+
+
+impl Default for UnfinishedMenu {
+    fn default() -> Self {
+        Self::new(String::default())
+    }
+}
+
+
+impl UnfinishedMenu {
+    pub fn new(menu: String) -> Self {
+        Self {
+            menu,
+        }
+    }
+
+    pub fn show_actions(&mut self, cache: &Cache) -> Result<()> {
+        self.generate_menu(cache)?;
+        print!("{}", self.menu);
+        stdout().flush()?;
+        Ok(())
+    }
+
+    pub async fn process_action(&mut self, action: String, cache: &mut Cache) -> Result<State> {
+        let index: usize = action.parse::<usize>()?
+            .checked_sub(1)
+            .context("Error during user input conversion")?;
+
+        match Action::arr().get(index).context("Error while using user input")? {
+            Action::Resume(_) => {
+                let failed: usize = cache.resume_unfinished().await?;
+                if failed > 0 {
+                    eprintln!("{failed} episode(s) failed to download");
+                }
+            },
+            Action::Delete(_) => println!("Deleted {} file(s)", cache.delete_unfinished()?),
+            Action::Keep(_) => {},
+        }
+        Ok(State::MainMenu)
+    }
+
+    fn generate_menu(&mut self, cache: &Cache) -> Result<()> {
+        let unfinished: Vec<Unfinished> = cache.unfinished()?;
+        let mut menu: String = format!("Found {} unfinished download(s):\n", unfinished.len());
+        for entry in &unfinished {
+            menu += match &entry.pending {
+                Some(pending) => format!("    {} - {} ({})\n", pending.anime, pending.episode.name, entry.part.display()),
+                None => format!("    {} (can't be resumed)\n", entry.part.display()),
+            }.as_str();
+        }
+        for (idx, action) in Action::arr().iter().enumerate() {
+            menu += format!("[{}] -> {}\n", idx + 1, action.text()).as_str();
+        }
+        self.menu = menu + "~$ ";
+        Ok(())
+    }
+}
+
+
+enum Action {
+    Resume(String),
+    Delete(String),
+    Keep(String),
+}
+
+
+impl Action {
+    fn arr() -> [Action; 3] {
+        [
+            Action::Resume(String::from("Resume them")),
+            Action::Delete(String::from("Delete them")),
+            Action::Keep(String::from("Keep them for later")),
+        ]
+    }
+
+    fn text(&self) -> String {
+        match self {
+            Self::Resume(text) => text,
+            Self::Delete(text) => text,
+            Self::Keep(text) => text,
+        }.clone()
+    }
+}
@@ -0,0 +1,197 @@
+use std::{
+    fs::{self, File},
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+
+use serde::{Deserialize, Serialize};
+
+use crate::anime::{Episode, EpisodeKey, Quality};
+
+
+/// The ledger of finished downloads, kept next to the cache.
+#[derive(Default, Serialize, Deserialize)]
+pub struct History {
+    #[serde(skip_serializing, skip_deserializing)]
+    path: PathBuf,
+    pub entries: Vec<HistoryEntry>,
+    #[serde(default)]
+    pub pending: Vec<PendingDownload>,
+}
+
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub anime: String,
+    pub key: EpisodeKey,
+    pub episode: String,
+    pub quality: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub checksum: String,
+    pub downloaded_at: u64,
+}
+
+
+/// A download that has started but not finished, so that its `.part` file
+/// can be resumed after a restart.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PendingDownload {
+    pub anime: String,
+    pub anime_url: String,
+    pub provider: String,
+    pub episode: Episode,
+    pub quality: Quality,
+    pub path: PathBuf,
+}
+
+
+/// A `.part` file left in the download directory, with the download it
+/// belongs to when that was recorded.
+pub struct Unfinished {
+    pub part: PathBuf,
+    pub pending: Option<PendingDownload>,
+}
+
+
+pub enum FileState {
+    Complete,
+    Missing,
+    /// The file is there, but its size or checksum isn't the recorded one.
+    Changed,
+}
+
+
+impl History {
+    /// Reads the ledger at `path`. One that can't be parsed is moved aside
+    /// to `<path>.bad` with a warning and replaced by an empty one, since
+    /// failing here would keep the program from starting at all.
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let mut history: Self = match path.exists() {
+            true => match serde_json::from_slice(&fs::read(&path)?) {
+                Ok(history) => history,
+                Err(e) => {
+                    let mut bad: PathBuf = path.clone();
+                    bad.as_mut_os_string().push(".bad");
+                    fs::rename(&path, &bad)
+                        .context(format!("Error moving the unreadable download history ({})", path.display()))?;
+                    eprintln!(
+                        "WARNING: The download history ({}) is unreadable ({e}), it was moved to {} and a new one was started",
+                        path.display(),
+                        bad.display(),
+                    );
+                    Self::default()
+                },
+            },
+            false => Self::default(),
+        };
+        history.path = path;
+        Ok(history)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(folder) = self.path.parent() {
+            fs::create_dir_all(folder)?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn find(&self, key: &EpisodeKey) -> Option<&HistoryEntry> {
+        self.entries.iter().find(|entry| entry.key == *key)
+    }
+
+    /// Adds the entry, replacing an older download of the same episode.
+    pub fn record(&mut self, entry: HistoryEntry) {
+        self.pending.retain(|p| p.path != entry.path);
+        self.entries.retain(|e| e.key != entry.key);
+        self.entries.push(entry);
+    }
+
+    pub fn start(&mut self, pending: PendingDownload) {
+        self.pending.retain(|p| p.path != pending.path);
+        self.pending.push(pending);
+    }
+
+    pub fn forget(&mut self, path: &Path) {
+        self.pending.retain(|p| p.path != path);
+    }
+}
+
+
+impl HistoryEntry {
+    /// Checks the file against the recorded size, and the checksum too when
+    /// `full` is set, since that reads the whole file.
+    pub fn check(&self, full: bool) -> Result<FileState> {
+        let size: u64 = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata.len(),
+            Err(_) => return Ok(FileState::Missing),
+        };
+        if size != self.size || (full && checksum(&self.path)? != self.checksum) {
+            return Ok(FileState::Changed);
+        }
+        Ok(FileState::Complete)
+    }
+}
+
+
+/// Every `.part` file under `dir`.
+pub fn find_parts(dir: &Path) -> Vec<PathBuf> {
+    let mut parts: Vec<PathBuf> = Vec::new();
+    let Ok(entries) = fs::read_dir(dir) else {
+        return parts;
+    };
+    for entry in entries.flatten() {
+        let path: PathBuf = entry.path();
+        if path.is_dir() {
+            parts.extend(find_parts(&path));
+        }
+        else if path.extension().is_some_and(|extension| extension == "part") {
+            parts.push(path);
+        }
+    }
+    parts.sort();
+    parts
+}
+
+
+/// FNV-1a over the file contents, prefixed with the algorithm name so that
+/// entries stay comparable if it ever changes.
+pub fn checksum(path: &Path) -> Result<String> {
+    let mut reader: BufReader<File> = BufReader::new(File::open(path)?);
+    let mut buffer: Vec<u8> = vec![0; 64 * 1024];
+    let mut hash: u64 = 0xcbf29ce484222325;
+    loop {
+        let read: usize = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        for byte in &buffer[..read] {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    Ok(format!("fnv1a64:{hash:016x}"))
+}
+
+
+/// A Unix timestamp as `YYYY-MM-DD HH:MM` in UTC.
+pub fn format_timestamp(timestamp: u64) -> String {
+    let days: i64 = (timestamp / 86400) as i64;
+    let minutes: u64 = timestamp % 86400 / 60;
+
+    // Howard Hinnant's days-to-civil conversion
+    let z: i64 = days + 719468;
+    let era: i64 = z.div_euclid(146097);
+    let day_of_era: i64 = z - era * 146097;
+    let year_of_era: i64 = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year: i64 = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp: i64 = (5 * day_of_year + 2) / 153;
+    let day: i64 = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month: i64 = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year: i64 = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!("{year:04}-{month:02}-{day:02} {:02}:{:02}", minutes / 60, minutes % 60)
+}
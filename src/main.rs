@@ -4,21 +4,53 @@ mod parser;
 mod cli;
 mod anime;
 mod settings;
+mod retry;
+mod selection;
+mod search;
+mod template;
+mod provider;
+mod transport;
+mod history;
+mod mp4;
+mod bandwidth;
 
 
-use crate::cli::CLI;
+use std::process::ExitCode;
+
+use clap::Parser;
+
+use crate::cache::Overrides;
+use crate::cli::{Args, CLI};
 
 
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
+    let args: Args = Args::parse();
+    let overrides: Overrides = args.overrides();
+
+    if let Some(command) = args.command {
+        return match command.run(overrides).await {
+            Ok(_) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("ERROR: {e}");
+                ExitCode::FAILURE
+            },
+        };
+    }
+
+    // Leftover downloads are only offered once, not after every restart
+    let mut check_unfinished: bool = true;
     loop {
         let mut cli: CLI = CLI::default();
-        match cli.start().await {
+        cli.set_overrides(overrides.clone());
+        match cli.start(check_unfinished).await {
             Ok(_) => break,
             Err(e) => {
                 eprintln!("CRITICAL ERROR: {e}");
                 println!("Restarting...");
             },
         }
+        check_unfinished = false;
     }
+    ExitCode::SUCCESS
 }
@@ -0,0 +1,104 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use anyhow::Result;
+
+
+/// Checks that an MP4 file isn't cut short by walking its top-level boxes:
+/// their sizes have to add up to the file size and a `moov` box (the index
+/// a player needs) has to be among them.
+///
+/// Returns `None` when the file doesn't start with an `ftyp` box, so it isn't
+/// an MP4 file and can't be checked this way.
+pub fn is_complete(path: &Path) -> Result<Option<bool>> {
+    let length: u64 = path.metadata()?.len();
+    let mut reader: BufReader<File> = BufReader::new(File::open(path)?);
+
+    let mut position: u64 = 0;
+    let mut has_moov: bool = false;
+    while position < length {
+        let mut header: [u8; 8] = [0; 8];
+        if position + 8 > length {
+            return Ok(Some(false));
+        }
+        reader.read_exact(&mut header)?;
+
+        let kind: &[u8] = &header[4..8];
+        if position == 0 && kind != b"ftyp" {
+            return Ok(None);
+        }
+        has_moov |= kind == b"moov";
+
+        let size: u64 = match u32::from_be_bytes([header[0], header[1], header[2], header[3]]) {
+            // The box runs to the end of the file
+            0 => length - position,
+            // The real size follows the header as a 64-bit number
+            1 => {
+                let mut size: [u8; 8] = [0; 8];
+                if position + 16 > length {
+                    return Ok(Some(false));
+                }
+                reader.read_exact(&mut size)?;
+                u64::from_be_bytes(size)
+            },
+            size => size as u64,
+        };
+        // A box can't be smaller than its header or run past the end of the file
+        if size < 8 || size > length - position {
+            return Ok(Some(false));
+        }
+
+        position = match position.checked_add(size) {
+            Some(position) => position,
+            None => return Ok(Some(false)),
+        };
+        reader.seek(SeekFrom::Start(position))?;
+    }
+
+    Ok(Some(position == length && has_moov))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+
+    fn check(name: &str, data: &[u8]) -> Option<bool> {
+        let path: PathBuf = std::env::temp_dir().join(format!("ando_2-mp4-{name}-{}.mp4", std::process::id()));
+        std::fs::write(&path, data).unwrap();
+        let complete: Option<bool> = is_complete(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        complete
+    }
+
+    fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut data: Vec<u8> = (8 + payload.len() as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(kind);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    #[test]
+    fn checks_box_sizes() {
+        let whole: Vec<u8> = [mp4_box(b"ftyp", b"isom"), mp4_box(b"moov", &[1; 16]), mp4_box(b"mdat", &[2; 64])].concat();
+        assert_eq!(check("whole", &whole), Some(true));
+        assert_eq!(check("cut", &whole[..whole.len() - 10]), Some(false));
+        assert_eq!(check("other", b"not a video at all"), None);
+    }
+
+    #[test]
+    fn rejects_huge_64_bit_sizes() {
+        let mut huge: Vec<u8> = [mp4_box(b"ftyp", b"isom"), mp4_box(b"moov", &[1; 16])].concat();
+        huge.extend_from_slice(&1u32.to_be_bytes());
+        huge.extend_from_slice(b"mdat");
+        huge.extend_from_slice(&u64::MAX.to_be_bytes());
+        huge.extend_from_slice(&[2; 64]);
+        assert_eq!(check("huge", &huge), Some(false));
+    }
+}
@@ -1,130 +1,741 @@
-use std::time::Duration;
+use std::{
+    fmt,
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
-use anyhow::{Result, bail, Context};
+use anyhow::{bail, Result, Context};
 
 use indicatif::{ProgressBar, ProgressStyle};
 
-use rayon::prelude::*;
-
-use reqwest::{Client, IntoUrl, Response};
+use reqwest::{
+    Certificate, Client, ClientBuilder, IntoUrl, NoProxy, Proxy, StatusCode, Url,
+    header::{ACCEPT_RANGES, CONTENT_TYPE, RANGE},
+    Method,
+};
 use tokio::{
-    time::sleep,
-    fs::{create_dir_all, File},
+    sync::Mutex,
+    time::{sleep, Instant},
+    fs::{self, create_dir_all, metadata, remove_file, rename, File, OpenOptions},
+    io::{AsyncSeekExt, AsyncWriteExt},
 };
-use futures::StreamExt;
+use futures::{future::join_all, StreamExt};
+
+use serde::{Deserialize, Serialize};
 
 use crate::anime::{Episode, Quality};
+use crate::bandwidth::{Bandwidth, DownloadLimit};
+use crate::mp4;
+use crate::settings::Settings;
+use crate::retry::{is_interrupted_transfer, is_retryable_error, is_retryable_status, RetryPolicy};
+use crate::transport::{HttpRequest, HttpResponse, Transport, TransportMode};
+
+
+pub const DEFAULT_BASE_URL: &str = "https://jut.su/";
+
+/// Episodes aren't split into segments smaller than this, and a segment's
+/// progress is saved every time this much more of it has been written.
+const MIN_SEGMENT_SIZE: u64 = 1024 * 1024;
 
 
 #[derive(Clone)]
 pub struct Net {
-    client: Client,
+    transport: Transport,
+    base_url: Url,
+    retry: RetryPolicy,
+    request_delay: Duration,
+    connections: usize,
+    bandwidth: Bandwidth,
+    last_request: Arc<Mutex<Option<Instant>>>,
 }
 
 
 impl Default for Net {
     fn default() -> Self {
-        Self::new(Client::default())
+        Self::new(
+            Transport::new(Client::default(), TransportMode::Live),
+            default_base_url(),
+            RetryPolicy::default(),
+            Duration::from_millis(250),
+            1,
+            Bandwidth::default(),
+        )
     }
 }
 
 
 impl Net {
-    pub fn new(client: Client) -> Self {
+    pub fn new(
+        transport: Transport,
+        base_url: Url,
+        retry: RetryPolicy,
+        request_delay: Duration,
+        connections: usize,
+        bandwidth: Bandwidth,
+    ) -> Self {
         Self {
-            client,
+            transport,
+            base_url,
+            retry,
+            request_delay,
+            connections: connections.max(1),
+            bandwidth,
+            last_request: Arc::new(Mutex::new(None)),
         }
     }
 
-    pub async fn get_anime_list_html(&self, from_page: &mut u64, pages: u64) -> Result<String> {
-        let spinner_style: ProgressStyle = ProgressStyle::with_template(
-            "{prefix:.bold.dim} {spinner} {wide_msg}"
-        )?.tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ");
-        let pb: ProgressBar = ProgressBar::new(pages);
-        pb.set_style(spinner_style);
-        pb.set_message("Fetching anime...");
-        if pages == 0 {
-            pb.set_prefix(format!("[{from_page}/?]"));
-        }
-        else {
-            pb.set_prefix(format!("[{from_page}/{pages}]"));
-        }
-        
-        let mut result: String = self.client.get("https://jut.su/anime/").send().await?.text().await?;
-
-        loop {
-            // TODO: Add all headers
-            let response: Response = self.client.post("https://jut.su/anime/")
-                .header("Content-Type", "application/x-www-form-urlencoded; charset=UTF-8")
-                .body(format!("ajax_load=yes&start_from_page={from_page}&show_search=&anime_of_user="))
-                .send().await?;
-
-            if response.status() != 200 {
-                bail!("Status code not 200");
-            }
+    /// Builds a `Net` from the settings. The bandwidth limits are passed in
+    /// rather than read from them, so that every `Net` built from the same
+    /// `Bandwidth` shares its budget.
+    pub fn from_settings(settings: &Settings, mode: TransportMode, bandwidth: Bandwidth) -> Result<Self> {
+        Ok(Self::new(
+            Transport::new(build_client(settings)?, mode),
+            parse_base_url(settings.base_url.as_str())?,
+            RetryPolicy::default().with_attempts(settings.retry_attempts),
+            Duration::from_millis(settings.request_delay),
+            settings.connections,
+            bandwidth,
+        ))
+    }
 
-            let body: String = response.text().await?;
+    pub fn base_url(&self) -> &Url {
+        &self.base_url
+    }
 
-            if body == "empty" {
-                break;
-            }
+    /// Resolves `path` against the base URL, e.g. `anime/`.
+    pub fn url(&self, path: &str) -> Result<Url> {
+        Ok(self.base_url.join(path)?)
+    }
 
-            result += body.as_str();
-            
-            pb.set_position(*from_page);
-            if pages == 0 {
-                pb.set_prefix(format!("[{from_page}/?]"));
-            }
-            else {
-                pb.set_prefix(format!("[{from_page}/{pages}]"));
-            }
+    /// Points a page URL saved in the cache at the base URL, keeping only its
+    /// path below `cached_under`, the base it was cached from, or below the
+    /// site root for entries that don't know it. So anime cached from one
+    /// address are fetched from the one configured now, e.g. a mirror or a
+    /// test server.
+    pub fn site_url(&self, url: &str, cached_under: Option<&str>) -> Result<Url> {
+        let url: Url = self.base_url.join(url)?;
+        if url.as_str().starts_with(self.base_url.as_str()) {
+            return Ok(url);
+        }
 
-            *from_page += 1;
+        let cached_under: Option<Url> = cached_under.and_then(|base| parse_base_url(base).ok());
+        if let Some(path) = cached_under.and_then(|base| url.as_str().strip_prefix(base.as_str()).map(String::from)) {
+            return self.url(path.as_str());
+        }
 
-            sleep(Duration::from_millis(250)).await;
+        let mut path: String = url.path().trim_start_matches('/').to_string();
+        if let Some(query) = url.query() {
+            path = format!("{path}?{query}");
         }
-        *from_page -= 1;
-        
-        pb.finish_with_message("Fetching done!");
+        self.url(path.as_str())
+    }
 
-        Ok(result)
+    /// Waits until at least `request_delay` has passed since the previous
+    /// paced request, shared by all clones of this `Net`, so concurrent
+    /// catalogue fetches stay as polite as sequential ones.
+    pub async fn pace(&self) {
+        let mut last_request = self.last_request.lock().await;
+        if let Some(last) = *last_request {
+            sleep(self.request_delay.saturating_sub(last.elapsed())).await;
+        }
+        *last_request = Some(Instant::now());
     }
     
-    pub async fn get_anime_html<URL: IntoUrl>(&self, anime_url: URL) -> Result<String> {
-        self.get_html(anime_url).await
+    pub async fn get_html<URL: IntoUrl>(&self, url: URL) -> Result<String> {
+        let url: Url = url.into_url()?;
+        self.send(&HttpRequest::get(url)).await?.error_for_status()?.text().await
     }
 
-    pub async fn get_episode_html<URL: IntoUrl>(&self, episode_url: URL) -> Result<String> {
-        self.get_html(episode_url).await
+    pub async fn post_form<URL: IntoUrl>(&self, url: URL, body: String) -> Result<String> {
+        let url: Url = url.into_url()?;
+        let request: HttpRequest = HttpRequest::post(url)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded; charset=UTF-8")
+            .body(body);
+        self.send(&request).await?.error_for_status()?.text().await
     }
 
-    async fn get_html<URL: IntoUrl>(&self, url: URL) -> Result<String> {
-        // TODO: Catch request code
-        Ok(self.client.get(url).send().await?.text().await?)
+    /// Sends the request, retrying connection failures and
+    /// retryable statuses (408, 429, 5xx) according to the retry policy.
+    /// Any other response, including 4xx, is returned to the caller as is.
+    async fn send(&self, request: &HttpRequest) -> Result<HttpResponse> {
+        let mut attempt: u32 = 0;
+        loop {
+            attempt += 1;
+            let delay: Duration = match self.transport.execute(request).await {
+                Ok(response) if !is_retryable_status(response.status()) => return Ok(response),
+                Ok(response) if attempt >= self.retry.attempts => return Ok(response),
+                Ok(response) => match self.retry.delay_for(&response, attempt) {
+                    Some(delay) => delay,
+                    None => return Ok(response),
+                },
+                Err(e) => {
+                    if attempt >= self.retry.attempts || !is_retryable_error(&e) {
+                        return Err(e);
+                    }
+                    self.retry.backoff(attempt)
+                },
+            };
+            sleep(delay).await;
+        }
     }
 
-    pub async fn download_episode(&self, episode: Episode, quality: Quality, pb: &ProgressBar) -> Result<()> {
-        let url: String = episode.quality
-            .par_iter()
-            .find_any(|&q| q.equal(&quality))
-            .context("Error while searching for the selected quality")?
-            .val();
-        let response: Response = self.client.get(url).send().await?;
-        // TODO: Check response
-        let size: u64 = response.content_length().context("Error while getting the episode size")?;
-        
-        pb.set_length(size);
+    pub async fn download_episode(&self, episode: Episode, quality: Quality, path: &Path, pb: &ProgressBar) -> Result<()> {
+        let url: Url = Url::parse(quality.val().as_str()).context("Error in the video link")?;
+
+        if let Some(folder) = path.parent() {
+            create_dir_all(folder).await?;
+        }
+
         pb.set_message(episode.name.clone());
 
-        create_dir_all("./data/anime/").await?;
-        let mut file: File = File::create(format!("./data/anime/{}.mp4", episode.name)).await?;
+        // A file already at `path` (from an older version or a changed
+        // download) is checked the same way as a partial one.
+        let part: PathBuf = part_path(path);
+        if metadata(&part).await.is_err() && metadata(path).await.is_ok() {
+            rename(path, &part).await?;
+        }
+
+        let limit: DownloadLimit = self.bandwidth.download();
+        let size: Option<u64> = match self.connections > 1 {
+            true => self.ranged_size(&url).await?,
+            false => None,
+        };
+        match size {
+            Some(size) => self.fetch_segments(&episode.name, &url, part.as_path(), size, &limit, pb).await?,
+            None => {
+                // A part written in segments has gaps, so a single stream
+                // can't continue it
+                let segments: PathBuf = segments_path(&part);
+                if metadata(&segments).await.is_ok() {
+                    remove_file(&part).await.ok();
+                    remove_file(&segments).await?;
+                }
+
+                let mut attempt: u32 = 0;
+                loop {
+                    attempt += 1;
+                    match self.fetch_episode(&url, part.as_path(), &limit, pb).await {
+                        Ok(()) => break,
+                        Err(e) if attempt < self.retry.attempts && is_interrupted_transfer(&e) => {
+                            pb.set_message(format!("{} (retrying: {e})", episode.name));
+                            sleep(self.retry.backoff(attempt)).await;
+                            pb.set_message(episode.name.clone());
+                        },
+                        Err(e) => return Err(e),
+                    }
+                }
+            },
+        }
+        rename(&part, path).await?;
+
+        pb.finish_with_message(format!("Downloading complete: {}", episode.name));
+        Ok(())
+    }
+
+    /// Streams the episode into `path`, continuing from whatever is already
+    /// on disk, so that a retry after a dropped connection picks up where
+    /// the previous attempt stopped. Fails unless the file ends up with the
+    /// size the server announced, or, when it announced none, unless
+    /// `check_unknown_size` finds the file complete.
+    async fn fetch_episode(&self, url: &Url, path: &Path, limit: &DownloadLimit, pb: &ProgressBar) -> Result<()> {
+        let downloaded: u64 = match metadata(path).await {
+            Ok(meta) => meta.len(),
+            Err(_) => 0,
+        };
+
+        let (response, mut file, size) = match self.resume_episode(url, downloaded).await? {
+            Resume::Partial(response) => {
+                let size: Option<u64> = response.content_range()
+                    .and_then(|(_, total)| total)
+                    .or(response.content_length().map(|len| downloaded + len));
+                self.show_size(pb, size)?;
+                pb.set_position(downloaded);
+                (*response, OpenOptions::new().append(true).open(path).await?, size)
+            },
+            Resume::Complete => {
+                pb.set_length(downloaded);
+                pb.set_position(downloaded);
+                return Ok(());
+            },
+            Resume::Restart => {
+                let response: HttpResponse = self.send(&HttpRequest::get(url.clone())).await?.error_for_status()?;
+                let size: Option<u64> = response.content_length();
+                self.show_size(pb, size)?;
+                pb.set_position(0);
+                (response, File::create(path).await?, size)
+            },
+        };
+
         let mut stream = response.bytes_stream();
         while let Some(bytes) = stream.next().await {
             let bytes = bytes?;
-            tokio::io::copy(&mut bytes.clone().as_ref(), &mut file).await?;
+            file.write_all(&bytes).await?;
             pb.inc(bytes.len() as u64);
+            limit.take(bytes.len() as u64).await;
+        }
+        file.flush().await?;
+
+        let received: u64 = metadata(path).await?.len();
+        match size {
+            Some(size) if received != size => Err(IncompleteDownload { expected: Some(size), received }.into()),
+            Some(_) => Ok(()),
+            None => self.check_unknown_size(url, path, received).await,
+        }
+    }
+
+    /// Without a size the bar can't show progress, so it becomes a spinner
+    /// counting the bytes received.
+    fn show_size(&self, pb: &ProgressBar, size: Option<u64>) -> Result<()> {
+        match size {
+            Some(size) => pb.set_length(size),
+            None => pb.set_style(ProgressStyle::with_template(
+                "{msg} -> {spinner:.green} [{elapsed_precise}] {bytes} ({bytes_per_sec})"
+            )?),
+        }
+        Ok(())
+    }
+
+    /// Decides whether a download of unknown size is complete: asking the
+    /// server for the bytes after the end tells if there are more, and when
+    /// it doesn't support ranges the MP4 structure is checked instead.
+    async fn check_unknown_size(&self, url: &Url, path: &Path, received: u64) -> Result<()> {
+        let incomplete = |expected: Option<u64>| -> Result<()> {
+            Err(IncompleteDownload { expected, received }.into())
+        };
+
+        match self.resume_episode(url, received).await? {
+            Resume::Complete => Ok(()),
+            Resume::Partial(response) => incomplete(response.content_range().and_then(|(_, total)| total)),
+            Resume::Restart => {
+                let path: PathBuf = path.to_path_buf();
+                match tokio::task::spawn_blocking(move || mp4::is_complete(&path)).await?? {
+                    Some(false) => incomplete(None),
+                    Some(true) | None => Ok(()),
+                }
+            },
+        }
+    }
+
+    /// The size of the video when the server accepts byte ranges for it and
+    /// it is large enough to be worth splitting, `None` otherwise.
+    async fn ranged_size(&self, url: &Url) -> Result<Option<u64>> {
+        let response: HttpResponse = self.send(&HttpRequest::new(Method::HEAD, url.clone())).await?;
+        let ranges: bool = response.headers()
+            .get(ACCEPT_RANGES)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.split(',').any(|unit| unit.trim() == "bytes"));
+
+        match response.content_length() {
+            Some(size) if response.status().is_success() && ranges && size >= 2 * MIN_SEGMENT_SIZE => Ok(Some(size)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Fetches the video as byte ranges over several connections at once,
+    /// writing each into its place in a preallocated `path`. The progress of
+    /// every segment is kept next to the file, so that a failed segment is
+    /// retried from where it stopped, alone, and an interrupted download
+    /// continues after a restart.
+    async fn fetch_segments(
+        &self,
+        name: &str,
+        url: &Url,
+        path: &Path,
+        size: u64,
+        limit: &DownloadLimit,
+        pb: &ProgressBar,
+    ) -> Result<()> {
+        let state_path: PathBuf = segments_path(path);
+        let state: Segments = self.load_segments(path, state_path.as_path(), size).await?;
+
+        let file: File = OpenOptions::new().write(true).create(true).truncate(false).open(path).await?;
+        file.set_len(size).await?;
+        drop(file);
+
+        pb.set_length(size);
+        pb.set_position(state.done());
+        let count: usize = state.segments.len();
+        let state: Mutex<Segments> = Mutex::new(state);
+        save_segments(&state_path, &*state.lock().await).await?;
+
+        let results: Vec<Result<()>> = join_all((0..count).map(|index| {
+            self.fetch_segment(name, url, path, &state, state_path.as_path(), index, limit, pb)
+        })).await;
+        let state: Segments = state.into_inner();
+        save_segments(&state_path, &state).await?;
+        results.into_iter().collect::<Result<()>>()?;
+
+        let received: u64 = state.done();
+        if received != size {
+            return Err(IncompleteDownload { expected: Some(size), received }.into());
+        }
+        remove_file(&state_path).await?;
+        Ok(())
+    }
+
+    /// Picks up the saved segments of an earlier attempt. Without them, a
+    /// part that a single stream left behind counts as downloaded and only
+    /// the rest of the file is split.
+    async fn load_segments(&self, path: &Path, state_path: &Path, size: u64) -> Result<Segments> {
+        if let Ok(saved) = fs::read(state_path).await {
+            if let Ok(state) = serde_json::from_slice::<Segments>(&saved) {
+                if state.size == size && metadata(path).await.is_ok() {
+                    return Ok(state);
+                }
+            }
+            // Left by a different version of the file, nothing in it can be trusted
+            remove_file(path).await.ok();
+            remove_file(state_path).await?;
+        }
+
+        let downloaded: u64 = match metadata(path).await {
+            Ok(meta) if meta.len() <= size => meta.len(),
+            Ok(_) => {
+                remove_file(path).await?;
+                0
+            },
+            Err(_) => 0,
+        };
+        Ok(Segments::split(size, downloaded, self.connections))
+    }
+
+    /// Fetches what is left of one segment, retrying it on its own.
+    #[allow(clippy::too_many_arguments)]
+    async fn fetch_segment(
+        &self,
+        name: &str,
+        url: &Url,
+        path: &Path,
+        state: &Mutex<Segments>,
+        state_path: &Path,
+        index: usize,
+        limit: &DownloadLimit,
+        pb: &ProgressBar,
+    ) -> Result<()> {
+        let mut attempt: u32 = 0;
+        loop {
+            attempt += 1;
+            let result: Result<()> = self.fetch_range(url, path, state, state_path, index, limit, pb).await;
+            match result {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt < self.retry.attempts && is_interrupted_transfer(&e) => {
+                    pb.set_message(format!("{name} (retrying part {}: {e})", index + 1));
+                    sleep(self.retry.backoff(attempt)).await;
+                    pb.set_message(name.to_string());
+                },
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn fetch_range(
+        &self,
+        url: &Url,
+        path: &Path,
+        state: &Mutex<Segments>,
+        state_path: &Path,
+        index: usize,
+        limit: &DownloadLimit,
+        pb: &ProgressBar,
+    ) -> Result<()> {
+        let (start, end) = state.lock().await.segments[index].remaining();
+        if start >= end {
+            return Ok(());
+        }
+
+        let request: HttpRequest = HttpRequest::get(url.clone()).header(RANGE, format!("bytes={start}-{}", end - 1));
+        let response: HttpResponse = self.send(&request).await?.error_for_status()?;
+        if response.status() != StatusCode::PARTIAL_CONTENT || response.content_range().map(|(first, _)| first) != Some(start) {
+            bail!("The server didn't return the requested part of the video");
+        }
+
+        let mut file: File = OpenOptions::new().write(true).open(path).await?;
+        file.seek(SeekFrom::Start(start)).await?;
+
+        let mut position: u64 = start;
+        let mut saved: u64 = start;
+        let mut stream = response.bytes_stream();
+        let result: Result<()> = async {
+            while position < end {
+                let Some(bytes) = stream.next().await else {
+                    break;
+                };
+                let bytes = bytes?;
+                // Servers may send more than was asked for
+                let length: usize = bytes.len().min((end - position) as usize);
+                file.write_all(&bytes[..length]).await?;
+                position += length as u64;
+                {
+                    let mut state = state.lock().await;
+                    state.segments[index].written = position - saved;
+                    pb.set_position(state.downloaded());
+                }
+                limit.take(length as u64).await;
+
+                if position - saved >= MIN_SEGMENT_SIZE {
+                    file.flush().await?;
+                    let mut state = state.lock().await;
+                    let segment: &mut Segment = &mut state.segments[index];
+                    segment.done = position - segment.start;
+                    segment.written = 0;
+                    saved = position;
+                    save_segments(state_path, &state).await?;
+                }
+            }
+            file.flush().await?;
+            Ok(())
+        }.await;
+
+        let mut state = state.lock().await;
+        state.segments[index].written = 0;
+        if let Err(e) = result {
+            // What came after the last save is fetched, and counted, again
+            pb.set_position(state.downloaded());
+            return Err(e);
+        }
+        let segment: &mut Segment = &mut state.segments[index];
+        segment.done = position - segment.start;
+        if position < end {
+            return Err(IncompleteDownload { expected: Some(end - segment.start), received: segment.done }.into());
         }
-        pb.finish_with_message(format!("Downloading complete: {}", episode.name));
         Ok(())
     }
+
+    async fn resume_episode(&self, url: &Url, downloaded: u64) -> Result<Resume> {
+        if downloaded == 0 {
+            return Ok(Resume::Restart);
+        }
+
+        let request: HttpRequest = HttpRequest::get(url.clone()).header(RANGE, format!("bytes={downloaded}-"));
+        let response: HttpResponse = self.send(&request).await?;
+
+        match response.status() {
+            StatusCode::PARTIAL_CONTENT => match response.content_range() {
+                Some((start, _)) if start == downloaded => Ok(Resume::Partial(Box::new(response))),
+                _ => Ok(Resume::Restart),
+            },
+            StatusCode::RANGE_NOT_SATISFIABLE => match response.content_range() {
+                Some((_, Some(total))) if total == downloaded => Ok(Resume::Complete),
+                _ => Ok(Resume::Restart),
+            },
+            _ => Ok(Resume::Restart),
+        }
+    }
+}
+
+
+
+/// Parses a base URL, adding the trailing `/` that `Url::join` needs to keep
+/// the last path segment (`http://host/mirror` -> `http://host/mirror/`).
+pub fn parse_base_url(url: &str) -> Result<Url> {
+    let mut url: Url = Url::parse(url.trim()).context(format!("Invalid base URL: {url}"))?;
+    if !url.path().ends_with('/') {
+        url.set_path(format!("{}/", url.path()).as_str());
+    }
+    Ok(url)
+}
+
+
+pub fn default_base_url() -> Url {
+    Url::parse(DEFAULT_BASE_URL).expect("The default base URL is valid")
+}
+
+
+/// The one client behind catalogue, page and video requests, going through
+/// the proxy from the settings and trusting those of their extra CA
+/// certificates that can be read.
+fn build_client(settings: &Settings) -> Result<Client> {
+    let mut client: ClientBuilder = Client::builder().user_agent(settings.user_agent.as_str());
+
+    if let Some(url) = &settings.proxy {
+        let mut proxy: Proxy = Proxy::all(url.as_str())
+            .context(format!("Invalid proxy: {url}"))?
+            .no_proxy(NoProxy::from_string(settings.no_proxy.as_str()));
+        if let Some(user) = &settings.proxy_user {
+            proxy = proxy.basic_auth(user.as_str(), settings.proxy_password.as_deref().unwrap_or_default());
+        }
+        client = client.proxy(proxy);
+    }
+
+    // A certificate that can't be read anymore mustn't keep the program, and
+    // with it the settings menu, from starting
+    for path in &settings.ca_certificates {
+        match load_certificates(std::slice::from_ref(path)) {
+            Ok(certificates) => for certificate in certificates {
+                client = client.add_root_certificate(certificate);
+            },
+            Err(e) => eprintln!("WARNING: {e:#}, continuing without it"),
+        }
+    }
+
+    Ok(client.build()?)
+}
+
+
+/// Reads CA certificates from PEM files, which may hold several, or from
+/// DER files.
+pub fn load_certificates(paths: &[String]) -> Result<Vec<Certificate>> {
+    let mut certificates: Vec<Certificate> = Vec::new();
+    for path in paths {
+        let data: Vec<u8> = std::fs::read(path).context(format!("Error reading the CA certificate ({path})"))?;
+        let parsed: reqwest::Result<Vec<Certificate>> = match data.windows(10).any(|window| window == b"-----BEGIN") {
+            true => Certificate::from_pem_bundle(&data),
+            false => Certificate::from_der(&data).map(|certificate| vec![certificate]),
+        };
+        certificates.extend(parsed.context(format!("Invalid CA certificate ({path})"))?);
+    }
+    Ok(certificates)
+}
+
+
+/// Where an episode is written until it is complete: `<path>.part`.
+pub fn part_path(path: &Path) -> PathBuf {
+    let mut part: PathBuf = path.to_path_buf();
+    part.as_mut_os_string().push(".part");
+    part
+}
+
+
+/// Where the progress of a download fetched in segments is kept:
+/// `<path>.part.segments` next to its part.
+pub fn segments_path(part: &Path) -> PathBuf {
+    let mut segments: PathBuf = part.to_path_buf();
+    segments.as_mut_os_string().push(".segments");
+    segments
+}
+
+
+async fn save_segments(path: &Path, state: &Segments) -> Result<()> {
+    fs::write(path, serde_json::to_vec(state)?).await?;
+    Ok(())
+}
+
+
+/// The body ended before (or after) the size the server announced, or
+/// before the end of a file whose size wasn't announced.
+#[derive(Debug)]
+pub struct IncompleteDownload {
+    pub expected: Option<u64>,
+    pub received: u64,
+}
+
+
+impl fmt::Display for IncompleteDownload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.expected {
+            Some(expected) => write!(f, "Downloaded {} of {} bytes", self.received, expected),
+            None => write!(f, "The download stopped after {} bytes before the end of the file", self.received),
+        }
+    }
+}
+
+
+impl std::error::Error for IncompleteDownload {}
+
+
+enum Resume {
+    Partial(Box<HttpResponse>),
+    Complete,
+    Restart,
+}
+
+
+#[derive(Serialize, Deserialize)]
+struct Segments {
+    size: u64,
+    segments: Vec<Segment>,
+}
+
+
+/// The bytes from `start` up to `end` (exclusive), of which the first
+/// `done` are on disk and the `written` after them are on their way.
+#[derive(Serialize, Deserialize)]
+struct Segment {
+    start: u64,
+    end: u64,
+    done: u64,
+    #[serde(skip_serializing, skip_deserializing)]
+    written: u64,
+}
+
+
+impl Segments {
+    /// Splits the bytes after `downloaded` into up to `connections` segments
+    /// of at least `MIN_SEGMENT_SIZE`.
+    fn split(size: u64, downloaded: u64, connections: usize) -> Self {
+        let mut segments: Vec<Segment> = vec![Segment { start: 0, end: downloaded, done: downloaded, written: 0 }];
+        let left: u64 = size - downloaded;
+        let count: u64 = (left / MIN_SEGMENT_SIZE).clamp(1, connections as u64);
+        for n in 0..count {
+            segments.push(Segment {
+                start: downloaded + left * n / count,
+                end: downloaded + left * (n + 1) / count,
+                done: 0,
+                written: 0,
+            });
+        }
+        segments.retain(|segment| segment.start < segment.end);
+        Self { size, segments }
+    }
+
+    fn done(&self) -> u64 {
+        self.segments.iter().map(|segment| segment.done).sum()
+    }
+
+    /// What the progress bar shows: the saved bytes and the ones after them.
+    fn downloaded(&self) -> u64 {
+        self.segments.iter().map(|segment| segment.done + segment.written).sum()
+    }
+}
+
+
+impl Segment {
+    fn remaining(&self) -> (u64, u64) {
+        (self.start + self.done, self.end)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn net(base_url: &str) -> Net {
+        let settings: Settings = Settings {
+            base_url: base_url.to_string(),
+            ..Settings::default()
+        };
+        Net::from_settings(&settings, TransportMode::Live, Bandwidth::default()).unwrap()
+    }
+
+    fn site_url(base_url: &str, url: &str, cached_under: Option<&str>) -> String {
+        net(base_url).site_url(url, cached_under).unwrap().to_string()
+    }
+
+    #[test]
+    fn keeps_urls_under_the_base() {
+        assert_eq!(site_url("https://jut.su/", "https://jut.su/naruto/", Some("https://jut.su/")), "https://jut.su/naruto/");
+        assert_eq!(site_url("https://jut.su/", "naruto/film-1.html", None), "https://jut.su/naruto/film-1.html");
+    }
+
+    #[test]
+    fn rebases_onto_the_base_they_were_cached_under() {
+        let mirror: Option<&str> = Some("http://127.0.0.1:8080/mirror/");
+        assert_eq!(site_url("https://jut.su/", "http://127.0.0.1:8080/mirror/naruto/", mirror), "https://jut.su/naruto/");
+        assert_eq!(
+            site_url("http://10.0.0.1/other/", "http://127.0.0.1:8080/mirror/naruto/episode-1.html?x=1", mirror),
+            "http://10.0.0.1/other/naruto/episode-1.html?x=1",
+        );
+        assert_eq!(site_url("http://127.0.0.1:8080/mirror/", "https://jut.su/naruto/", Some("https://jut.su/")), "http://127.0.0.1:8080/mirror/naruto/");
+    }
+
+    #[test]
+    fn rebases_from_the_root_without_a_known_base() {
+        assert_eq!(site_url("https://jut.su/", "http://127.0.0.1:8080/naruto/", None), "https://jut.su/naruto/");
+        // The cached base doesn't match the URL, so only the host can be replaced
+        assert_eq!(site_url("https://jut.su/", "http://127.0.0.1:8080/naruto/", Some("http://other/")), "https://jut.su/naruto/");
+    }
 }
@@ -4,29 +4,33 @@ use anyhow::{Result, Context};
 
 use select::{
     document::Document,
-    predicate::{Class, Name},
+    node::Node,
+    predicate::{Attr, Class, Name},
 };
 
-use crate::anime::{Anime, Episode, Quality};
+use reqwest::Url;
+
+use crate::anime::{Anime, Episode, Metadata, Quality};
+use crate::net::default_base_url;
 
 
 #[derive(Clone)]
 pub struct Parser {
-
+    base_url: Url,
 }
 
 
 impl Default for Parser {
     fn default() -> Self {
-        Self::new()
+        Self::new(default_base_url())
     }
 }
 
 
 impl Parser {
-    pub fn new() -> Self {
+    pub fn new(base_url: Url) -> Self {
         Self {
-
+            base_url,
         }
     }
 
@@ -39,7 +43,8 @@ impl Parser {
                 .context("Error when searching for an anime link")?.to_string();
             let url: &str = node.first_child().context("Error when searching for an anime link")?
                 .attr("href").context("Error when searching for an anime link")?;
-            let anime: Anime = Anime::new(name, format!("https://jut.su{url}"), Vec::default());
+            let mut anime: Anime = Anime::new(name, self.base_url.join(url)?, Vec::default());
+            anime.metadata.poster = self.parse_poster(node);
             result.push(anime);
         }
         
@@ -53,21 +58,102 @@ impl Parser {
         for node in document.find(Class("short-btn")) {
             let name: String = node.text();
             let url: &str = node.attr("href").context("Error when searching for an anime link")?;
-            let episode: Episode = Episode::new(name, format!("https://jut.su{url}"), BTreeSet::new());
-            anime.episodes.push(episode);
+            let episode: Episode = Episode::new(name, self.base_url.join(url)?, BTreeSet::new());
+            anime.add_episode(episode);
         }
+        anime.metadata = self.parse_metadata(&document);
 
         Ok(anime)
     }
 
+    /// Reads the anime details. The description is the text under the
+    /// player and the rest comes from the block of `Label: <a>value</a>`
+    /// lines below it, e.g. `Жанры: <a>Приключения</a>, <a>Комедия</a>`.
+    fn parse_metadata(&self, document: &Document) -> Metadata {
+        let mut metadata: Metadata = Metadata {
+            description: document
+                .find(Class("under_video"))
+                .next()
+                .map(|node| node.text())
+                .or_else(|| meta_content(document, "og:description"))
+                .unwrap_or_default()
+                .trim()
+                .to_string(),
+            poster: meta_content(document, "og:image")
+                .and_then(|poster| Some(self.base_url.join(&poster).ok()?.to_string())),
+            ..Metadata::default()
+        };
+
+        let Some(block) = document.find(Class("under_video_additional")).next() else {
+            return metadata;
+        };
+
+        let mut label: String = String::new();
+        for node in block.children() {
+            if let Some(text) = node.as_text() {
+                if let Some((before, after)) = text.rsplit_once(':') {
+                    label = before.rsplit(['\n', '.']).next().unwrap_or_default().trim().to_lowercase();
+                    add_metadata(&mut metadata, label.as_str(), after.trim().trim_matches(',').trim());
+                }
+                continue;
+            }
+            add_metadata(&mut metadata, label.as_str(), node.text().trim());
+        }
+
+        metadata
+    }
+
+    /// The poster of a catalogue entry, given as `background: url('...')`.
+    fn parse_poster(&self, node: Node) -> Option<String> {
+        let style: &str = node
+            .descendants()
+            .find_map(|node| node.attr("style").filter(|style| style.contains("url(")))?;
+        let url: &str = style
+            .split_once("url(")?.1
+            .split_once(')')?.0
+            .trim_matches(['\'', '"', ' ']);
+        Some(self.base_url.join(url).ok()?.to_string())
+    }
+
     pub fn parse_episode(&self, episode_html: String) -> Result<Episode> {
         let mut episode: Episode = Episode::default();
 
         let document: Document = Document::from(episode_html.as_str());
         for node in document.find(Name("source")) {
-            episode.quality.insert(Quality::from(node)?);
+            // A source that can't be read shouldn't hide the others
+            if let Ok(quality) = Quality::from(node, &self.base_url) {
+                episode.quality.insert(quality);
+            }
         }
 
         Ok(episode)
     }
 }
+
+
+fn meta_content(document: &Document, property: &str) -> Option<String> {
+    document
+        .find(Attr("property", property))
+        .next()?
+        .attr("content")
+        .map(|content| content.to_string())
+}
+
+
+fn add_metadata(metadata: &mut Metadata, label: &str, value: &str) {
+    if value.is_empty() {
+        return;
+    }
+    if label.starts_with("жанр") {
+        metadata.genres.push(value.to_string());
+    }
+    else if label.starts_with("год") {
+        metadata.years.extend(value.split(|c: char| !c.is_ascii_digit()).filter_map(|year| year.parse::<u32>().ok()));
+    }
+    else if label.contains("рейтинг") {
+        metadata.age_rating = Some(value.to_string());
+    }
+    else if label.contains("название") {
+        metadata.alt_titles.push(value.to_string());
+    }
+}
@@ -0,0 +1,89 @@
+use anyhow::Result;
+
+use futures::future::BoxFuture;
+
+use crate::anime::{Anime, Episode};
+use crate::net::Net;
+use crate::parser::Parser;
+use crate::provider::Provider;
+
+
+pub const ID: &str = "jut.su";
+
+
+#[derive(Clone)]
+pub struct JutSu {
+    net: Net,
+    parser: Parser,
+}
+
+
+impl JutSu {
+    pub fn new(net: Net) -> Self {
+        Self {
+            net: net.clone(),
+            parser: Parser::new(net.base_url().clone()),
+        }
+    }
+
+    async fn get_catalogue_page(&self, page: u64) -> Result<Option<Vec<Anime>>> {
+        self.net.pace().await;
+
+        let html: String = match page {
+            0 | 1 => self.net.get_html(self.net.url("anime/")?).await?,
+            // TODO: Add all headers
+            page => self.net.post_form(
+                self.net.url("anime/")?,
+                format!("ajax_load=yes&start_from_page={page}&show_search=&anime_of_user="),
+            ).await?,
+        };
+
+        if html == "empty" {
+            return Ok(None);
+        }
+
+        let mut anime: Vec<Anime> = self.parser.parse_anime_list(html)?;
+        for entry in anime.iter_mut() {
+            entry.provider = String::from(ID);
+            entry.base_url = Some(self.net.base_url().to_string());
+        }
+        Ok(Some(anime))
+    }
+
+    async fn get_anime(&self, anime: &Anime) -> Result<Anime> {
+        let anime_html: String = self.net.get_html(self.net.site_url(anime.url.as_str(), anime.base_url.as_deref())?).await?;
+        let mut result: Anime = self.parser.parse_anime(anime_html)?;
+        result.provider = String::from(ID);
+        for episode in result.episodes_mut() {
+            episode.provider = String::from(ID);
+            episode.base_url = Some(self.net.base_url().to_string());
+        }
+        Ok(result)
+    }
+
+    async fn get_episode(&self, episode: &Episode) -> Result<Episode> {
+        let episode_html: String = self.net.get_html(self.net.site_url(episode.url.as_str(), episode.base_url.as_deref())?).await?;
+        let mut result: Episode = episode.clone();
+        result.quality = self.parser.parse_episode(episode_html)?.quality;
+        Ok(result)
+    }
+}
+
+
+impl Provider for JutSu {
+    fn id(&self) -> &'static str {
+        ID
+    }
+
+    fn catalogue_page(&self, page: u64) -> BoxFuture<'_, Result<Option<Vec<Anime>>>> {
+        Box::pin(self.get_catalogue_page(page))
+    }
+
+    fn anime<'a>(&'a self, anime: &'a Anime) -> BoxFuture<'a, Result<Anime>> {
+        Box::pin(self.get_anime(anime))
+    }
+
+    fn episode<'a>(&'a self, episode: &'a Episode) -> BoxFuture<'a, Result<Episode>> {
+        Box::pin(self.get_episode(episode))
+    }
+}
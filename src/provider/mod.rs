@@ -0,0 +1,40 @@
+mod jutsu;
+
+
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use futures::future::BoxFuture;
+
+use crate::anime::{Anime, Episode};
+use crate::net::Net;
+
+pub use crate::provider::jutsu::JutSu;
+
+
+/// A site anime can be downloaded from.
+///
+/// `Net` stays site-agnostic (requests, retries, downloads), a provider knows
+/// the site's URLs and markup.
+pub trait Provider: Send + Sync {
+    /// A stable identifier saved with every cached anime.
+    fn id(&self) -> &'static str;
+
+    /// One page of the catalogue, starting from 1, or `None` past the last one.
+    /// The episode lists of the returned anime are empty.
+    fn catalogue_page(&self, page: u64) -> BoxFuture<'_, Result<Option<Vec<Anime>>>>;
+
+    /// The anime page with its episode list.
+    fn anime<'a>(&'a self, anime: &'a Anime) -> BoxFuture<'a, Result<Anime>>;
+
+    /// The episode with its stream URLs resolved.
+    fn episode<'a>(&'a self, episode: &'a Episode) -> BoxFuture<'a, Result<Episode>>;
+}
+
+
+pub fn all(net: &Net) -> Vec<Arc<dyn Provider>> {
+    vec![
+        Arc::new(JutSu::new(net.clone())),
+    ]
+}
@@ -0,0 +1,180 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Error;
+
+use reqwest::{
+    StatusCode,
+    header::RETRY_AFTER,
+};
+
+use crate::net::IncompleteDownload;
+use crate::transport::{HttpResponse, StatusError};
+
+
+#[derive(Clone)]
+pub struct RetryPolicy {
+    pub attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new(5, Duration::from_millis(500), Duration::from_secs(30))
+    }
+}
+
+
+impl RetryPolicy {
+    pub fn new(attempts: u32, base_delay: Duration, max_delay: Duration) -> Self {
+        Self {
+            attempts: attempts.max(1),
+            base_delay,
+            max_delay,
+        }
+    }
+
+    pub fn with_attempts(self, attempts: u32) -> Self {
+        Self::new(attempts, self.base_delay, self.max_delay)
+    }
+
+    /// Exponential backoff with full jitter: a random delay in
+    /// `[0, min(max_delay, base_delay * 2^attempt)]`.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let ceiling: Duration = self.base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        let random: u64 = RandomState::new().build_hasher().finish();
+        ceiling.mul_f64((random % 1_000) as f64 / 1_000.0)
+    }
+
+    /// The delay before the next attempt after a retryable response,
+    /// honoring `Retry-After` (in seconds or as an HTTP date) for 429 and 503.
+    /// `None` when the server asks to wait longer than `max_delay`, since
+    /// then it is better to give up.
+    pub fn delay_for(&self, response: &HttpResponse, attempt: u32) -> Option<Duration> {
+        let retry_after: Option<Duration> = match response.status() {
+            StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => response.headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(parse_retry_after),
+            _ => None,
+        };
+
+        match retry_after {
+            Some(delay) if delay > self.max_delay => None,
+            Some(delay) => Some(delay),
+            None => Some(self.backoff(attempt)),
+        }
+    }
+}
+
+
+pub fn is_retryable_status(status: StatusCode) -> bool {
+    status.is_server_error()
+        || status == StatusCode::REQUEST_TIMEOUT
+        || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+
+pub fn is_retryable_error(error: &Error) -> bool {
+    if error.downcast_ref::<IncompleteDownload>().is_some() {
+        return true;
+    }
+    if let Some(e) = error.downcast_ref::<StatusError>() {
+        return is_retryable_status(e.status);
+    }
+    match error.downcast_ref::<reqwest::Error>() {
+        Some(e) => match e.status() {
+            Some(status) => is_retryable_status(status),
+            // A connection dropped mid-body surfaces as a decode error
+            None => e.is_timeout() || e.is_connect() || e.is_request() || e.is_body() || e.is_decode(),
+        },
+        None => false,
+    }
+}
+
+
+/// Whether the error cut a response body short. `Net::send` only sees the
+/// start of a response, so downloads retry these themselves, continuing from
+/// where the body stopped; everything else `send` has already retried.
+pub fn is_interrupted_transfer(error: &Error) -> bool {
+    if error.downcast_ref::<IncompleteDownload>().is_some() {
+        return true;
+    }
+    match error.downcast_ref::<reqwest::Error>() {
+        Some(e) => e.is_body() || e.is_decode(),
+        None => false,
+    }
+}
+
+
+/// `Retry-After` is either a number of seconds or an HTTP date
+/// (`Sun, 06 Nov 1994 08:49:37 GMT`), which is turned into the time left.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value: &str = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date: SystemTime = parse_http_date(value)?;
+    Some(date.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+
+/// Parses the IMF-fixdate form of an HTTP date, the only one servers
+/// are allowed to send.
+fn parse_http_date(value: &str) -> Option<SystemTime> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    let [_, day, month, year, time, "GMT"] = parts.as_slice() else {
+        return None;
+    };
+
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let month: i64 = MONTHS.iter().position(|name| name == month)? as i64 + 1;
+    let day: i64 = day.parse().ok()?;
+    let year: i64 = year.parse().ok()?;
+    let time: Vec<u64> = time.split(':').map(|part| part.parse().ok()).collect::<Option<Vec<u64>>>()?;
+    let [hours, minutes, seconds] = time.as_slice() else {
+        return None;
+    };
+
+    // Howard Hinnant's days-from-civil conversion
+    let year: i64 = if month <= 2 { year - 1 } else { year };
+    let era: i64 = year.div_euclid(400);
+    let year_of_era: i64 = year - era * 400;
+    let day_of_year: i64 = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era: i64 = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days: u64 = u64::try_from(era * 146097 + day_of_era - 719468).ok()?;
+
+    Some(UNIX_EPOCH + Duration::from_secs(days * 86400 + hours * 3600 + minutes * 60 + seconds))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_http_dates() {
+        let date: SystemTime = parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT").unwrap();
+        assert_eq!(date, UNIX_EPOCH + Duration::from_secs(784111777));
+        let date: SystemTime = parse_http_date("Thu, 29 Feb 2024 23:59:59 GMT").unwrap();
+        assert_eq!(date, UNIX_EPOCH + Duration::from_secs(1709251199));
+
+        assert!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT").is_none());
+        assert!(parse_http_date("Sun, 06 Foo 1994 08:49:37 GMT").is_none());
+    }
+
+    #[test]
+    fn parses_retry_after() {
+        assert_eq!(parse_retry_after(" 120 "), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("Sun, 06 Nov 1994 08:49:37 GMT"), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+}
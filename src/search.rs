@@ -0,0 +1,253 @@
+use anyhow::{Context, Result};
+
+use crate::anime::Anime;
+
+
+/// Query words less similar than this to every word of a title miss it.
+const MIN_WORD_SCORE: f64 = 0.6;
+
+
+/// A catalogue search: words of the name plus `genre:<name>` and
+/// `year:<year>` filters, e.g. `naruto genre:комедия year:2002`.
+///
+/// The name is compared with the title, the alternative titles and the URL
+/// slug after lowercasing and transliterating Cyrillic to Latin, so `naruto`,
+/// `наруто` and `Наруто` are the same query and a typo still finds the anime.
+///
+/// The filters look at the anime metadata, which is only known for anime
+/// whose page has been opened at least once.
+pub struct SearchQuery {
+    pub name: String,
+    pub genres: Vec<String>,
+    pub year: Option<u32>,
+}
+
+
+impl SearchQuery {
+    pub fn parse(query: &str) -> Result<Self> {
+        let mut name: Vec<&str> = Vec::new();
+        let mut genres: Vec<String> = Vec::new();
+        let mut year: Option<u32> = None;
+
+        for word in query.split_whitespace() {
+            if let Some(genre) = word.strip_prefix("genre:") {
+                genres.push(normalize(genre));
+            }
+            else if let Some(value) = word.strip_prefix("year:") {
+                year = Some(value.parse().context(format!("Invalid year \"{value}\""))?);
+            }
+            else {
+                name.push(word);
+            }
+        }
+
+        Ok(Self {
+            name: normalize(name.join(" ").as_str()),
+            genres,
+            year,
+        })
+    }
+
+    /// How well the anime matches, from 0 to 1, or `None` if it doesn't.
+    pub fn score(&self, anime: &Anime) -> Option<f64> {
+        let genres: bool = self.genres.iter().all(|genre| {
+            anime.metadata.genres.iter().any(|g| normalize(g).contains(genre.as_str()))
+        });
+        let year: bool = self.year.is_none_or(|year| anime.metadata.years.contains(&year));
+        if !genres || !year {
+            return None;
+        }
+
+        if self.name.is_empty() {
+            return Some(1.0);
+        }
+        titles(anime)
+            .map(|title| title_score(self.name.as_str(), title.as_str()))
+            .max_by(f64::total_cmp)
+            .filter(|score| *score > 0.0)
+    }
+
+    /// The name is exactly one of the titles, up to case and transliteration.
+    pub fn is_exact(&self, anime: &Anime) -> bool {
+        titles(anime).any(|title| title == self.name)
+    }
+}
+
+
+fn titles(anime: &Anime) -> impl Iterator<Item = String> + '_ {
+    std::iter::once(&anime.name)
+        .chain(&anime.metadata.alt_titles)
+        .map(|title| normalize(title))
+        .chain(std::iter::once(normalize(anime.slug().as_str())))
+        .filter(|title| !title.is_empty())
+}
+
+
+/// A whole-string match scores highest, then a substring, then titles whose
+/// words are close to every query word.
+fn title_score(query: &str, title: &str) -> f64 {
+    if title == query {
+        return 1.0;
+    }
+    if title.contains(query) {
+        return 0.9 + 0.05 * query.len() as f64 / title.len() as f64;
+    }
+
+    let words: Vec<&str> = title.split(' ').collect();
+    let scores: Vec<f64> = query
+        .split(' ')
+        .map(|q| {
+            words
+                .iter()
+                .map(|w| word_score(q, w))
+                .fold(0.0, f64::max)
+        })
+        .collect();
+    if scores.iter().any(|score| *score < MIN_WORD_SCORE) {
+        return 0.0;
+    }
+    // Titles with fewer extra words rank higher.
+    let coverage: f64 = scores.len().min(words.len()) as f64 / words.len() as f64;
+    (0.8 + 0.05 * coverage) * scores.iter().sum::<f64>() / scores.len() as f64
+}
+
+
+fn word_score(query: &str, word: &str) -> f64 {
+    if word.starts_with(query) && query.chars().count() >= 3 {
+        return 0.95;
+    }
+    let query: Vec<char> = query.chars().collect();
+    let word: Vec<char> = word.chars().collect();
+    let length: usize = query.len().max(word.len());
+    if length == 0 {
+        return 0.0;
+    }
+    1.0 - distance(&query, &word) as f64 / length as f64
+}
+
+
+/// Edit distance where swapping two neighbouring letters is one typo.
+fn distance(a: &[char], b: &[char]) -> usize {
+    let mut d: Vec<Vec<usize>> = (0..=a.len())
+        .map(|i| (0..=b.len()).map(|j| if i == 0 { j } else if j == 0 { i } else { 0 }).collect())
+        .collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost: usize = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j - 1] + cost).min(d[i - 1][j] + 1).min(d[i][j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+
+/// Lowercases, transliterates Cyrillic to Latin and turns punctuation into
+/// single spaces, e.g. `Наруто: Ураганные хроники` -> `naruto uragannye hroniki`.
+fn normalize(text: &str) -> String {
+    let mut result: String = String::new();
+    for c in text.to_lowercase().chars() {
+        match transliterate(c) {
+            Some(latin) => result += latin,
+            None if c.is_alphanumeric() => result.push(c),
+            None => result.push(' '),
+        }
+    }
+    result.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+
+fn transliterate(c: char) -> Option<&'static str> {
+    Some(match c {
+        'а' => "a", 'б' => "b", 'в' => "v", 'г' => "g", 'д' => "d",
+        'е' | 'ё' | 'э' => "e", 'ж' => "zh", 'з' => "z", 'и' => "i", 'й' => "y",
+        'к' => "k", 'л' => "l", 'м' => "m", 'н' => "n", 'о' => "o",
+        'п' => "p", 'р' => "r", 'с' => "s", 'т' => "t", 'у' => "u",
+        'ф' => "f", 'х' => "h", 'ц' => "ts", 'ч' => "ch", 'ш' => "sh",
+        'щ' => "sch", 'ъ' | 'ь' => "", 'ы' => "y", 'ю' => "yu", 'я' => "ya",
+        _ => return None,
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::anime::Metadata;
+
+    /// The slugs don't resemble the names, so only the titles can match.
+    fn catalogue() -> Vec<Anime> {
+        let mut naruto: Anime = Anime::new("Наруто", "https://jut.su/a1/", Vec::new());
+        naruto.metadata = Metadata {
+            genres: vec![String::from("Приключения"), String::from("Комедия")],
+            years: vec![2002, 2007],
+            ..Metadata::default()
+        };
+        let mut alchemist: Anime = Anime::new("Стальной алхимик", "https://jut.su/a4/", Vec::new());
+        alchemist.metadata.alt_titles = vec![String::from("Fullmetal Alchemist")];
+        vec![
+            Anime::new("Нарута", "https://jut.su/a2/", Vec::new()),
+            Anime::new("Наруто: Ураганные хроники", "https://jut.su/a3/", Vec::new()),
+            naruto,
+            alchemist,
+            Anime::new("Блич", "https://jut.su/a5/", Vec::new()),
+        ]
+    }
+
+    /// The names of the matching anime, best first, as `Cache::get_anime_name` orders them.
+    fn search(query: &str) -> Vec<String> {
+        let query: SearchQuery = SearchQuery::parse(query).unwrap();
+        let catalogue: Vec<Anime> = catalogue();
+        let mut found: Vec<(f64, &Anime)> = catalogue
+            .iter()
+            .filter_map(|anime| Some((query.score(anime)?, anime)))
+            .collect();
+        found.sort_by(|a, b| b.0.total_cmp(&a.0));
+        found.into_iter().map(|(_, anime)| anime.name.clone()).collect()
+    }
+
+    #[test]
+    fn transliterates_cyrillic() {
+        assert_eq!(normalize("Наруто: Ураганные хроники!"), "naruto uragannye hroniki");
+        assert_eq!(search("naruto")[0], "Наруто");
+        assert_eq!(search("наруто")[0], "Наруто");
+        assert_eq!(search("НАРУТО")[0], "Наруто");
+        assert!(SearchQuery::parse("naruto").unwrap().is_exact(&catalogue()[2]));
+    }
+
+    #[test]
+    fn tolerates_typos() {
+        assert_eq!(search("narto")[0], "Наруто");
+        assert_eq!(search("nrauto")[0], "Наруто");
+        assert_eq!(distance(&['a', 'b'], &['b', 'a']), 1);
+        assert_eq!(search("bleach"), ["Блич"]);
+        assert!(search("gintama").is_empty());
+    }
+
+    #[test]
+    fn ranks_exact_then_substring_then_fuzzy() {
+        assert_eq!(search("naruto"), ["Наруто", "Наруто: Ураганные хроники", "Нарута"]);
+        assert!(title_score("naruto", "naruto") > title_score("naruto", "naruto uragannye hroniki"));
+        assert!(title_score("naruto", "naruto uragannye hroniki") > title_score("naruto", "naruta"));
+    }
+
+    #[test]
+    fn filters_by_genre_and_year() {
+        assert_eq!(search("genre:комедия"), ["Наруто"]);
+        assert_eq!(search("genre:komediya year:2007"), ["Наруто"]);
+        assert_eq!(search("naruto year:2002"), ["Наруто"]);
+        assert!(search("naruto year:2003").is_empty());
+        assert!(search("genre:драма").is_empty());
+        assert_eq!(SearchQuery::parse("year:soon").err().unwrap().to_string(), "Invalid year \"soon\"");
+    }
+
+    #[test]
+    fn matches_alternative_titles() {
+        assert_eq!(search("fullmetal"), ["Стальной алхимик"]);
+        assert_eq!(search("fulmetal alchemist"), ["Стальной алхимик"]);
+        assert!(SearchQuery::parse("Fullmetal Alchemist").unwrap().is_exact(&catalogue()[3]));
+    }
+}
@@ -0,0 +1,301 @@
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Error, Result};
+
+use crate::anime::{Episode, EpisodeKind};
+
+
+/// An episode selection such as `1-5,8,10-`, `last 3`, `s2e1-s2e12` or `!7`.
+///
+/// Terms are separated by commas. Plain numbers are positions in the
+/// episode list (starting from 1), `sXeY` is episode `Y` of season `X`,
+/// `sX` is the whole season and a leading `!` excludes the term. A selection
+/// made only of exclusions starts from all episodes.
+#[derive(Clone)]
+pub struct Selection {
+    include: Vec<Term>,
+    exclude: Vec<Term>,
+}
+
+
+#[derive(Clone)]
+enum Term {
+    All,
+    Last(usize),
+    Season(u32),
+    Range(Option<Position>, Option<Position>),
+}
+
+
+#[derive(Clone, Copy)]
+enum Position {
+    Index(usize),
+    Episode(u32, u32),
+}
+
+
+impl FromStr for Selection {
+    type Err = Error;
+
+    fn from_str(expression: &str) -> Result<Self> {
+        let mut include: Vec<Term> = Vec::new();
+        let mut exclude: Vec<Term> = Vec::new();
+
+        for part in expression.split(',').map(str::trim) {
+            if part.is_empty() {
+                continue;
+            }
+            match part.strip_prefix('!') {
+                Some(part) => exclude.push(Term::parse(part.trim())?),
+                None => include.push(Term::parse(part)?),
+            }
+        }
+
+        if include.is_empty() && exclude.is_empty() {
+            bail!("The episode selection is empty");
+        }
+        if include.is_empty() {
+            include.push(Term::All);
+        }
+
+        Ok(Self {
+            include,
+            exclude,
+        })
+    }
+}
+
+
+impl Selection {
+    pub fn parse(expression: &str) -> Result<Self> {
+        expression.parse()
+    }
+
+    /// Resolves the selection against `episodes`, keeping their order.
+    pub fn resolve(&self, episodes: &[Episode]) -> Result<Vec<Episode>> {
+        let mut selected: Vec<bool> = vec![false; episodes.len()];
+
+        for term in &self.include {
+            for idx in term.indices(episodes)? {
+                selected[idx] = true;
+            }
+        }
+        for term in &self.exclude {
+            for idx in term.indices(episodes)? {
+                selected[idx] = false;
+            }
+        }
+
+        Ok(episodes
+            .iter()
+            .zip(selected)
+            .filter(|(_, selected)| *selected)
+            .map(|(episode, _)| episode.clone())
+            .collect())
+    }
+}
+
+
+impl Term {
+    fn parse(term: &str) -> Result<Self> {
+        let lowercase: String = term.to_lowercase();
+        let term: &str = lowercase.as_str();
+
+        if term == "all" || term == "*" {
+            return Ok(Self::All);
+        }
+
+        if let Some(count) = term.strip_prefix("last") {
+            let count: usize = match count.trim() {
+                "" => 1,
+                count => count.parse().context(format!("Invalid episode count in \"{term}\""))?,
+            };
+            return Ok(Self::Last(count));
+        }
+
+        if let Some(season) = term.strip_prefix('s').filter(|s| !s.contains(['e', '-'])) {
+            return Ok(Self::Season(season.parse().context(format!("Invalid season in \"{term}\""))?));
+        }
+
+        match term.split_once('-') {
+            Some((start, end)) => Ok(Self::Range(Position::parse_bound(start)?, Position::parse_bound(end)?)),
+            None => {
+                let position: Position = Position::parse(term)?;
+                Ok(Self::Range(Some(position), Some(position)))
+            },
+        }
+    }
+
+    fn indices(&self, episodes: &[Episode]) -> Result<Vec<usize>> {
+        Ok(match self {
+            Self::All => (0..episodes.len()).collect(),
+            Self::Last(count) => {
+                let start: usize = episodes.len().checked_sub(*count).context(format!(
+                    "Can't take the last {count} episodes (the anime has {} episodes)",
+                    episodes.len(),
+                ))?;
+                (start..episodes.len()).collect()
+            },
+            Self::Season(season) => {
+                let indices: Vec<usize> = episodes
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(idx, episode)| (episode.kind == EpisodeKind::Episode && episode.season == *season).then_some(idx))
+                    .collect();
+                if indices.is_empty() {
+                    bail!("Season {season} does not exist");
+                }
+                indices
+            },
+            Self::Range(start, end) => {
+                let start: usize = match start {
+                    Some(position) => position.index(episodes)?,
+                    None => 0,
+                };
+                let end: usize = match end {
+                    Some(position) => position.index(episodes)?,
+                    None => episodes.len().checked_sub(1).context("The anime has no episodes")?,
+                };
+                if start > end {
+                    bail!("The range {}-{} is reversed", start + 1, end + 1);
+                }
+                (start..=end).collect()
+            },
+        })
+    }
+}
+
+
+impl Position {
+    fn parse(position: &str) -> Result<Self> {
+        let position: &str = position.trim();
+
+        if let Some((season, episode)) = position.strip_prefix('s').and_then(|p| p.split_once('e')) {
+            return Ok(Self::Episode(
+                season.parse().context(format!("Invalid season in \"{position}\""))?,
+                episode.parse().context(format!("Invalid episode in \"{position}\""))?,
+            ));
+        }
+
+        let index: usize = position.parse().context(format!("Invalid episode number \"{position}\""))?;
+        if index == 0 {
+            bail!("Episodes are numbered from 1");
+        }
+        Ok(Self::Index(index))
+    }
+
+    fn parse_bound(bound: &str) -> Result<Option<Self>> {
+        match bound.trim() {
+            "" => Ok(None),
+            bound => Ok(Some(Self::parse(bound)?)),
+        }
+    }
+
+    fn index(&self, episodes: &[Episode]) -> Result<usize> {
+        match *self {
+            Self::Index(index) => {
+                if index > episodes.len() {
+                    bail!("Episode {index} does not exist (the anime has {} episodes)", episodes.len());
+                }
+                Ok(index - 1)
+            },
+            Self::Episode(season, number) => episodes
+                .iter()
+                .position(|e| e.kind == EpisodeKind::Episode && e.season == season && e.number == Some(number))
+                .context(format!("Episode s{season}e{number} does not exist")),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+
+    fn episodes() -> Vec<Episode> {
+        [
+            "season-1/episode-1", "season-1/episode-2", "season-1/episode-3",
+            "season-2/episode-1", "season-2/episode-2", "season-2/episode-3",
+            "film-1",
+        ]
+            .iter()
+            .map(|page| Episode::new("", format!("https://jut.su/naruto/{page}.html"), BTreeSet::new()))
+            .collect()
+    }
+
+    /// The positions (from 1) of the selected episodes.
+    fn select(expression: &str) -> Vec<usize> {
+        let episodes: Vec<Episode> = episodes();
+        Selection::parse(expression).unwrap()
+            .resolve(&episodes).unwrap()
+            .iter()
+            .map(|episode| episodes.iter().position(|e| e.url == episode.url).unwrap() + 1)
+            .collect()
+    }
+
+    fn error(expression: &str) -> String {
+        match Selection::parse(expression).and_then(|selection| selection.resolve(&episodes())) {
+            Ok(_) => panic!("\"{expression}\" was accepted"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn selects_positions_and_ranges() {
+        assert_eq!(select("1-3,5,6-"), [1, 2, 3, 5, 6, 7]);
+        assert_eq!(select("-2"), [1, 2]);
+        assert_eq!(select("4"), [4]);
+        assert_eq!(select(" 2 , 2-3 "), [2, 3]);
+        assert_eq!(select("all"), [1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(select("*"), [1, 2, 3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn selects_the_last_episodes() {
+        assert_eq!(select("last 3"), [5, 6, 7]);
+        assert_eq!(select("LAST"), [7]);
+        assert_eq!(select("last 7"), [1, 2, 3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn selects_seasons_and_season_episodes() {
+        assert_eq!(select("s2e1-s2e3"), [4, 5, 6]);
+        assert_eq!(select("s1e2"), [2]);
+        assert_eq!(select("s2"), [4, 5, 6]);
+        assert_eq!(select("s1e3-"), [3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn excludes_episodes() {
+        assert_eq!(select("!7"), [1, 2, 3, 4, 5, 6]);
+        assert_eq!(select("s1,!2"), [1, 3]);
+        assert_eq!(select("1-5, ! s2e1"), [1, 2, 3, 5]);
+    }
+
+    #[test]
+    fn reports_invalid_selections() {
+        assert_eq!(error(""), "The episode selection is empty");
+        assert_eq!(error(" , "), "The episode selection is empty");
+        assert_eq!(error("last x"), "Invalid episode count in \"last x\"");
+        assert_eq!(error("last 8"), "Can't take the last 8 episodes (the anime has 7 episodes)");
+        assert_eq!(error("sx"), "Invalid season in \"sx\"");
+        assert_eq!(error("s3"), "Season 3 does not exist");
+        assert_eq!(error("sxe1"), "Invalid season in \"sxe1\"");
+        assert_eq!(error("s1ex"), "Invalid episode in \"s1ex\"");
+        assert_eq!(error("s1e9"), "Episode s1e9 does not exist");
+        assert_eq!(error("x"), "Invalid episode number \"x\"");
+        assert_eq!(error("0"), "Episodes are numbered from 1");
+        assert_eq!(error("8"), "Episode 8 does not exist (the anime has 7 episodes)");
+        assert_eq!(error("5-2"), "The range 5-2 is reversed");
+    }
+
+    #[test]
+    fn reports_a_range_over_no_episodes() {
+        let error: String = Selection::parse("1-").unwrap().resolve(&[]).err().unwrap().to_string();
+        assert_eq!(error, "Episode 1 does not exist (the anime has 0 episodes)");
+        let error: String = Selection::parse("-").unwrap().resolve(&[]).err().unwrap().to_string();
+        assert_eq!(error, "The anime has no episodes");
+    }
+}
@@ -1,75 +1,411 @@
 use std::collections::HashMap;
 
-use anyhow::{Result, Context};
+use anyhow::{bail, Context, Result};
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::anime::{Quality, QualityFallback};
+use crate::bandwidth::{format_rate, parse_rate};
+use crate::net::{load_certificates, parse_base_url, DEFAULT_BASE_URL};
+use crate::template::FilenameTemplate;
 
 
 #[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Settings {
-    pub settings: HashMap<String, bool>,
+    pub update_found_anime: bool,
+    pub anime_max_age: u64,
+    pub download_dir: String,
+    pub default_quality: String,
+    pub quality_fallback: QualityFallback,
+    pub default_concurrency: usize,
+    pub connections: usize,
+    pub limit_rate: Option<u64>,
+    pub download_limit_rate: Option<u64>,
+    pub filename_template: FilenameTemplate,
+    pub request_delay: u64,
+    pub retry_attempts: u32,
+    pub base_url: String,
+    pub proxy: Option<String>,
+    pub proxy_user: Option<String>,
+    pub proxy_password: Option<String>,
+    pub no_proxy: String,
+    pub ca_certificates: Vec<String>,
+    pub user_agent: String,
 }
 
 
 impl Default for Settings {
     fn default() -> Self {
-        let mut settings: HashMap<String, bool> = HashMap::default();
-        for option in Options::arr() {
-            settings.insert(option.val(), false);
-        }
-        Self::new(settings)
+        Self::new(
+            false,
+            24,
+            "./data/anime/",
+            "720p",
+            QualityFallback::default(),
+            1,
+            1,
+            None,
+            None,
+            FilenameTemplate::default(),
+            250,
+            5,
+            DEFAULT_BASE_URL,
+            None,
+            None,
+            None,
+            "",
+            Vec::new(),
+            format!("ando_2/{}", env!("CARGO_PKG_VERSION")),
+        )
     }
 }
 
 
 impl Settings {
-    pub fn new(settings: HashMap<String, bool>) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new<Dir: ToString, Q: ToString, URL: ToString, NP: ToString, UA: ToString>(
+        update_found_anime: bool,
+        anime_max_age: u64,
+        download_dir: Dir,
+        default_quality: Q,
+        quality_fallback: QualityFallback,
+        default_concurrency: usize,
+        connections: usize,
+        limit_rate: Option<u64>,
+        download_limit_rate: Option<u64>,
+        filename_template: FilenameTemplate,
+        request_delay: u64,
+        retry_attempts: u32,
+        base_url: URL,
+        proxy: Option<String>,
+        proxy_user: Option<String>,
+        proxy_password: Option<String>,
+        no_proxy: NP,
+        ca_certificates: Vec<String>,
+        user_agent: UA,
+    ) -> Self {
         Self {
-            settings,
+            update_found_anime,
+            anime_max_age,
+            download_dir: download_dir.to_string(),
+            default_quality: default_quality.to_string(),
+            quality_fallback,
+            default_concurrency,
+            connections,
+            limit_rate,
+            download_limit_rate,
+            filename_template,
+            request_delay,
+            retry_attempts,
+            base_url: base_url.to_string(),
+            proxy,
+            proxy_user,
+            proxy_password,
+            no_proxy: no_proxy.to_string(),
+            ca_certificates,
+            user_agent: user_agent.to_string(),
+        }
+    }
+
+    pub fn get(&self, option: &Options) -> String {
+        match option {
+            Options::UpdateFoundAnime(_) => self.update_found_anime.to_string(),
+            Options::AnimeMaxAge(_) => format!("{} h", self.anime_max_age),
+            Options::DownloadDir(_) => self.download_dir.clone(),
+            Options::DefaultQuality(_) => self.default_quality.clone(),
+            Options::QualityFallback(_) => self.quality_fallback.name(),
+            Options::DefaultConcurrency(_) => self.default_concurrency.to_string(),
+            Options::Connections(_) => self.connections.to_string(),
+            Options::LimitRate(_) => format_rate(self.limit_rate),
+            Options::DownloadLimitRate(_) => format_rate(self.download_limit_rate),
+            Options::FilenameTemplate(_) => self.filename_template.val(),
+            Options::RequestDelay(_) => format!("{} ms", self.request_delay),
+            Options::RetryAttempts(_) => self.retry_attempts.to_string(),
+            Options::BaseUrl(_) => self.base_url.clone(),
+            Options::Proxy(_) => self.proxy.clone().unwrap_or_else(|| String::from("none")),
+            Options::ProxyUser(_) => self.proxy_user.clone().unwrap_or_else(|| String::from("none")),
+            Options::ProxyPassword(_) => String::from(match self.proxy_password {
+                Some(_) => "********",
+                None => "none",
+            }),
+            Options::NoProxy(_) => match self.no_proxy.is_empty() {
+                true => String::from("none"),
+                false => self.no_proxy.clone(),
+            },
+            Options::CaCertificates(_) => match self.ca_certificates.is_empty() {
+                true => String::from("none"),
+                false => self.ca_certificates.join(", "),
+            },
+            Options::UserAgent(_) => self.user_agent.clone(),
         }
     }
 
-    pub fn change_option(&mut self, option: String) -> Result<()> {
-        self.check_settings();
-        
-        let state: bool = self.settings
-            .get(&option)
-            .context("Error when trying to change settings")? ^ true;
-        self.settings.insert(option, state);
+    pub fn set(&mut self, option: &Options, value: &str) -> Result<()> {
+        let value: &str = value.trim();
+        match option {
+            Options::UpdateFoundAnime(_) => self.update_found_anime = value.parse()
+                .context("Expected true or false")?,
+            Options::AnimeMaxAge(_) => self.anime_max_age = value
+                .trim_end_matches('h')
+                .trim()
+                .parse()
+                .context("Expected an age in hours")?,
+            Options::DownloadDir(_) => {
+                if value.is_empty() {
+                    bail!("The download directory can't be empty");
+                }
+                self.download_dir = value.to_string();
+            },
+            Options::DefaultQuality(_) => self.default_quality = Quality::from_name(value)?.name(),
+            Options::QualityFallback(_) => self.quality_fallback = QualityFallback::from_name(value)?,
+            Options::DefaultConcurrency(_) => {
+                self.default_concurrency = value.parse().context("Expected a number of episodes")?;
+                if self.default_concurrency < 1 {
+                    bail!("At least one episode must be downloaded at a time");
+                }
+            },
+            Options::Connections(_) => {
+                self.connections = value.parse().context("Expected a number of connections")?;
+                if self.connections < 1 {
+                    bail!("An episode needs at least one connection");
+                }
+            },
+            Options::LimitRate(_) => self.limit_rate = parse_rate(value)?,
+            Options::DownloadLimitRate(_) => self.download_limit_rate = parse_rate(value)?,
+            Options::FilenameTemplate(_) => self.filename_template = FilenameTemplate::parse(value)?,
+            Options::RequestDelay(_) => self.request_delay = value
+                .trim_end_matches("ms")
+                .trim()
+                .parse()
+                .context("Expected a delay in milliseconds")?,
+            Options::RetryAttempts(_) => {
+                self.retry_attempts = value.parse().context("Expected a number of attempts")?;
+                if self.retry_attempts < 1 {
+                    bail!("Every request needs at least one attempt");
+                }
+            },
+            Options::BaseUrl(_) => self.base_url = parse_base_url(value)?.to_string(),
+            Options::Proxy(_) => self.proxy = match value {
+                "" | "none" => None,
+                proxy => Some(reqwest::Proxy::all(proxy).map(|_| proxy.to_string())?),
+            },
+            Options::ProxyUser(_) => self.proxy_user = match value {
+                "" | "none" => None,
+                user => Some(user.to_string()),
+            },
+            Options::ProxyPassword(_) => self.proxy_password = match value {
+                "" => None,
+                password => Some(password.to_string()),
+            },
+            Options::NoProxy(_) => self.no_proxy = match value {
+                "none" => String::new(),
+                hosts => hosts.split(',').map(str::trim).filter(|host| !host.is_empty()).collect::<Vec<&str>>().join(","),
+            },
+            Options::CaCertificates(_) => {
+                let paths: Vec<String> = match value {
+                    "none" => Vec::new(),
+                    paths => paths.split(',').map(str::trim).filter(|path| !path.is_empty()).map(String::from).collect(),
+                };
+                load_certificates(&paths)?;
+                self.ca_certificates = paths;
+            },
+            Options::UserAgent(_) => {
+                if value.is_empty() {
+                    bail!("The user agent can't be empty");
+                }
+                self.user_agent = value.to_string();
+            },
+        }
         Ok(())
     }
-    
-    fn check_settings(&mut self) {
-        if self.settings.len() != Options::arr().len() {
-            *self = Self::default();
-            return;
+
+    pub fn toggle(&mut self, option: &Options) -> Result<()> {
+        match option {
+            Options::UpdateFoundAnime(_) => self.update_found_anime ^= true,
+            _ => bail!("Only on/off settings can be toggled"),
         }
+        Ok(())
+    }
 
+    /// Carries over the on/off settings older versions kept in a map keyed
+    /// by their menu text, the rest start from the defaults.
+    fn from_legacy(legacy: HashMap<String, bool>) -> Self {
+        let mut settings: Self = Self::default();
         for option in Options::arr() {
-            if !self.settings.contains_key(&option.val()) {
-                *self = Self::default();
-                return;
+            if let (OptionKind::Toggle, Some(state)) = (option.kind(), legacy.get(&option.val())) {
+                let _ = settings.set(&option, state.to_string().as_str());
             }
         }
+        settings
     }
 }
 
 
+/// Reads saved settings, including the `{"settings": {<menu text>: bool}}`
+/// form of older versions, which is written back in the current form with
+/// the next save.
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Settings, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Saved {
+        Legacy { settings: HashMap<String, bool> },
+        Current(Box<Settings>),
+    }
+
+    Ok(match Saved::deserialize(deserializer)? {
+        Saved::Legacy { settings } => Settings::from_legacy(settings),
+        Saved::Current(settings) => *settings,
+    })
+}
+
+
 pub enum Options {
     UpdateFoundAnime(String),
+    AnimeMaxAge(String),
+    DownloadDir(String),
+    DefaultQuality(String),
+    QualityFallback(String),
+    DefaultConcurrency(String),
+    Connections(String),
+    LimitRate(String),
+    DownloadLimitRate(String),
+    FilenameTemplate(String),
+    RequestDelay(String),
+    RetryAttempts(String),
+    BaseUrl(String),
+    Proxy(String),
+    ProxyUser(String),
+    ProxyPassword(String),
+    NoProxy(String),
+    CaCertificates(String),
+    UserAgent(String),
+}
+
+
+pub enum OptionKind {
+    Toggle,
+    Choice(Vec<String>),
+    Text,
 }
 
 
 impl Options {
-    pub fn arr() -> [Self; 1] {
+    pub fn arr() -> [Self; 19] {
         [
             Self::UpdateFoundAnime(String::from("Update the anime that you have already searched for")),
+            Self::AnimeMaxAge(String::from("Use cached episode lists younger than")),
+            Self::DownloadDir(String::from("Download directory")),
+            Self::DefaultQuality(String::from("Default quality")),
+            Self::QualityFallback(String::from("When an episode lacks the chosen quality")),
+            Self::DefaultConcurrency(String::from("Default number of simultaneous downloads")),
+            Self::Connections(String::from("Connections per episode")),
+            Self::LimitRate(String::from("Bandwidth limit for all downloads")),
+            Self::DownloadLimitRate(String::from("Bandwidth limit per download")),
+            Self::FilenameTemplate(String::from("Filename template")),
+            Self::RequestDelay(String::from("Delay between catalogue requests")),
+            Self::RetryAttempts(String::from("Attempts per request")),
+            Self::BaseUrl(String::from("Site address")),
+            Self::Proxy(String::from("Proxy")),
+            Self::ProxyUser(String::from("Proxy user")),
+            Self::ProxyPassword(String::from("Proxy password")),
+            Self::NoProxy(String::from("Hosts reached without the proxy")),
+            Self::CaCertificates(String::from("Extra CA certificates")),
+            Self::UserAgent(String::from("User agent")),
         ]
     }
-    
+
     pub fn val(&self) -> String {
         match self {
             Self::UpdateFoundAnime(text) => text,
+            Self::AnimeMaxAge(text) => text,
+            Self::DownloadDir(text) => text,
+            Self::DefaultQuality(text) => text,
+            Self::QualityFallback(text) => text,
+            Self::DefaultConcurrency(text) => text,
+            Self::Connections(text) => text,
+            Self::LimitRate(text) => text,
+            Self::DownloadLimitRate(text) => text,
+            Self::FilenameTemplate(text) => text,
+            Self::RequestDelay(text) => text,
+            Self::RetryAttempts(text) => text,
+            Self::BaseUrl(text) => text,
+            Self::Proxy(text) => text,
+            Self::ProxyUser(text) => text,
+            Self::ProxyPassword(text) => text,
+            Self::NoProxy(text) => text,
+            Self::CaCertificates(text) => text,
+            Self::UserAgent(text) => text,
         }.clone()
     }
+
+    pub fn kind(&self) -> OptionKind {
+        match self {
+            Self::UpdateFoundAnime(_) => OptionKind::Toggle,
+            Self::DefaultQuality(_) => OptionKind::Choice(Quality::common().iter().map(|q| q.name()).collect()),
+            Self::QualityFallback(_) => OptionKind::Choice(QualityFallback::arr().iter().map(|f| f.name()).collect()),
+            _ => OptionKind::Text,
+        }
+    }
+
+    pub fn hint(&self) -> String {
+        String::from(match self {
+            Self::FilenameTemplate(_) => "Fields: {anime}, {season}, {episode}, {title}, {quality}; numbers accept {episode:02}",
+            Self::AnimeMaxAge(_) => "Hours, used while the anime update setting is off",
+            Self::QualityFallback(_) => "exact: skip the episode, at-or-below: the best lower one, best: the best one, nearest: the closest one",
+            Self::Connections(_) => "Parts of a large episode fetched in parallel, if the server allows it; 1 to disable",
+            Self::LimitRate(_) | Self::DownloadLimitRate(_) => "Per second, e.g. 500K or 5M, `none` to disable",
+            Self::RequestDelay(_) => "Milliseconds",
+            Self::RetryAttempts(_) => "Connection errors, timeouts, 408, 429 and 5xx responses are retried until this many attempts were made",
+            Self::BaseUrl(_) => "e.g. https://jut.su/ or the address of a mirror",
+            Self::Proxy(_) => "e.g. http://host:3128 or socks5h://127.0.0.1:1080, `none` to disable",
+            Self::ProxyUser(_) => "Used with the proxy password, `none` if the proxy needs no login",
+            Self::ProxyPassword(_) => "Leave empty to remove it",
+            Self::NoProxy(_) => "Comma-separated hosts, domains (.example.com) or IP ranges (10.0.0.0/8), `none` to clear",
+            Self::CaCertificates(_) => "Comma-separated PEM or DER files trusted on top of the system ones, `none` to clear",
+            _ => "",
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Saved {
+        #[serde(deserialize_with = "deserialize")]
+        settings: Settings,
+    }
+
+    fn load(json: &str) -> Settings {
+        serde_json::from_str::<Saved>(json).unwrap().settings
+    }
+
+    #[test]
+    fn migrates_the_old_settings_map() {
+        let settings: Settings = load(r#"{"settings": {"settings": {"Update the anime that you have already searched for": true}}}"#);
+        assert!(settings.update_found_anime);
+        assert_eq!(settings.retry_attempts, Settings::default().retry_attempts);
+
+        let settings: Settings = load(r#"{"settings": {"settings": {"Update the anime that you have already searched for": false, "Gone": true}}}"#);
+        assert!(!settings.update_found_anime);
+    }
+
+    #[test]
+    fn reads_the_current_settings() {
+        let saved: String = format!(r#"{{"settings": {}}}"#, serde_json::to_string(&Settings {
+            update_found_anime: true,
+            connections: 3,
+            ..Settings::default()
+        }).unwrap());
+        let settings: Settings = load(saved.as_str());
+        assert!(settings.update_found_anime);
+        assert_eq!(settings.connections, 3);
+
+        let settings: Settings = load(r#"{"settings": {"update_found_anime": true}}"#);
+        assert!(settings.update_found_anime);
+        assert_eq!(settings.connections, Settings::default().connections);
+    }
 }
@@ -0,0 +1,221 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+
+use serde::{Deserialize, Serialize};
+
+
+const MAX_FIELD_LENGTH: usize = 100;
+/// File systems count names in bytes, usually up to 255 of them, which leaves
+/// room around a value for the rest of the template.
+const MAX_FIELD_BYTES: usize = 200;
+/// More than any season or episode number needs.
+const MAX_PAD_WIDTH: usize = 10;
+
+
+/// A relative path with `{field}` or `{field:0N}` placeholders, for example
+/// `{anime}/Season {season:02}/{episode:02} - {title}.mp4`.
+///
+/// Known fields are `anime`, `season`, `episode`, `title` and `quality`.
+/// Every substituted value is sanitized on its own, so a `/` inside a title
+/// never creates a folder, only the `/` written in the template itself does.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct FilenameTemplate {
+    template: String,
+}
+
+
+pub struct TemplateFields {
+    pub anime: String,
+    pub season: u32,
+    pub episode: u32,
+    pub title: String,
+    pub quality: String,
+}
+
+
+impl Default for FilenameTemplate {
+    fn default() -> Self {
+        Self::new("{anime}/Season {season:02}/{episode:02} - {title}.mp4")
+    }
+}
+
+
+impl FilenameTemplate {
+    pub fn new<T: ToString>(template: T) -> Self {
+        Self {
+            template: template.to_string(),
+        }
+    }
+
+    /// Creates a template after checking that it renders.
+    pub fn parse(template: &str) -> Result<Self> {
+        let template: Self = Self::new(template);
+        template.render(&TemplateFields {
+            anime: String::from("Anime"),
+            season: 1,
+            episode: 1,
+            title: String::from("Title"),
+            quality: String::from("720p"),
+        })?;
+        Ok(template)
+    }
+
+    pub fn val(&self) -> String {
+        self.template.clone()
+    }
+
+    pub fn render(&self, fields: &TemplateFields) -> Result<PathBuf> {
+        let mut result: String = String::new();
+        let mut rest: &str = self.template.as_str();
+
+        while let Some(start) = rest.find('{') {
+            result += &rest[..start];
+            let end: usize = rest[start..]
+                .find('}')
+                .context(format!("Unclosed placeholder in the filename template \"{}\"", self.template))?;
+            result += Self::field(&rest[start + 1..start + end], fields)?.as_str();
+            rest = &rest[start + end + 1..];
+        }
+        result += rest;
+
+        let path: PathBuf = result
+            .split('/')
+            .map(|part| part.trim().trim_end_matches('.'))
+            .filter(|part| !part.is_empty())
+            .collect();
+        if path.as_os_str().is_empty() {
+            bail!("The filename template \"{}\" produced an empty path", self.template);
+        }
+        Ok(path)
+    }
+
+    fn field(placeholder: &str, fields: &TemplateFields) -> Result<String> {
+        let (name, format) = match placeholder.split_once(':') {
+            Some((name, format)) => (name.trim(), Some(format.trim())),
+            None => (placeholder.trim(), None),
+        };
+
+        let number: Option<u32> = match name {
+            "season" => Some(fields.season),
+            "episode" => Some(fields.episode),
+            _ => None,
+        };
+
+        Ok(match (number, format) {
+            (Some(number), Some(format)) => {
+                let width: usize = format
+                    .trim_start_matches('0')
+                    .parse()
+                    .context(format!("Invalid format \"{format}\" for the field \"{name}\""))?;
+                if width > MAX_PAD_WIDTH {
+                    bail!("The field \"{name}\" can't be padded to more than {MAX_PAD_WIDTH} digits");
+                }
+                format!("{number:0width$}")
+            },
+            (Some(number), None) => number.to_string(),
+            (None, Some(_)) => bail!("The field \"{name}\" does not support formatting"),
+            (None, None) => sanitize(match name {
+                "anime" => fields.anime.as_str(),
+                "title" => fields.title.as_str(),
+                "quality" => fields.quality.as_str(),
+                _ => bail!("Unknown field \"{name}\" in the filename template"),
+            }),
+        })
+    }
+}
+
+
+/// Makes a value safe to use as a single path component: characters that
+/// are invalid on common file systems become `_`, and long values are cut.
+pub fn sanitize(value: &str) -> String {
+    let mut bytes: usize = 0;
+    let value: String = value
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => ' ',
+            c => c,
+        })
+        .take(MAX_FIELD_LENGTH)
+        .take_while(|c| {
+            bytes += c.len_utf8();
+            bytes <= MAX_FIELD_BYTES
+        })
+        .collect();
+    let value: &str = value.trim().trim_end_matches('.');
+
+    match value {
+        "" => String::from("_"),
+        value => value.to_string(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    fn fields(anime: &str, title: &str) -> TemplateFields {
+        TemplateFields {
+            anime: anime.to_string(),
+            season: 2,
+            episode: 7,
+            title: title.to_string(),
+            quality: String::from("720p"),
+        }
+    }
+
+    fn render(anime: &str, title: &str) -> PathBuf {
+        FilenameTemplate::default().render(&fields(anime, title)).unwrap()
+    }
+
+    #[test]
+    fn renders_the_default_template() {
+        assert_eq!(render("Naruto", "Homecoming"), Path::new("Naruto/Season 02/07 - Homecoming.mp4"));
+        let template: FilenameTemplate = FilenameTemplate::parse("{anime} [{quality}]/{season}x{episode:03}.mp4").unwrap();
+        assert_eq!(template.render(&fields("Naruto", "")).unwrap(), Path::new("Naruto [720p]/2x007.mp4"));
+    }
+
+    #[test]
+    fn keeps_values_inside_their_path_component() {
+        assert_eq!(render("Fate/Zero", "Who: are you?"), Path::new("Fate_Zero/Season 02/07 - Who_ are you_.mp4"));
+        assert_eq!(render("..", "../../etc/passwd"), Path::new("_/Season 02/07 - .._.._etc_passwd.mp4"));
+        assert_eq!(render("Naruto...", "."), Path::new("Naruto/Season 02/07 - _.mp4"));
+        assert_eq!(render("\\*\"<>|", "\tTab"), Path::new("______/Season 02/07 - Tab.mp4"));
+        let template: FilenameTemplate = FilenameTemplate::parse("../{anime}/./{title}.mp4").unwrap();
+        assert_eq!(template.render(&fields("Naruto", "Title")).unwrap(), Path::new("Naruto/Title.mp4"));
+    }
+
+    #[test]
+    fn cuts_long_values() {
+        assert_eq!(sanitize("a".repeat(150).as_str()), "a".repeat(MAX_FIELD_LENGTH));
+
+        let title: String = "Наруто".repeat(30);
+        let cut: String = sanitize(title.as_str());
+        assert_eq!(cut.chars().count(), MAX_FIELD_LENGTH);
+        assert!(title.starts_with(cut.as_str()));
+        let path: PathBuf = render(title.as_str(), title.as_str());
+        assert!(path.iter().all(|part| part.len() <= 255));
+
+        let cut: String = sanitize("語".repeat(100).as_str());
+        assert!(cut.len() <= MAX_FIELD_BYTES);
+        assert_eq!(cut, "語".repeat(MAX_FIELD_BYTES / 3));
+    }
+
+    #[test]
+    fn rejects_broken_templates() {
+        let error = |template: &str| FilenameTemplate::parse(template).err().unwrap().to_string();
+        assert_eq!(error("{anime"), "Unclosed placeholder in the filename template \"{anime\"");
+        assert_eq!(error("{name}.mp4"), "Unknown field \"name\" in the filename template");
+        assert_eq!(error("{title:02}"), "The field \"title\" does not support formatting");
+        assert_eq!(error("{episode:x}"), "Invalid format \"x\" for the field \"episode\"");
+        assert_eq!(error("{anime}/{episode:099999999999}.mp4"), "The field \"episode\" can't be padded to more than 10 digits");
+        assert_eq!(error("{season:99999999999999999999999}"), "Invalid format \"99999999999999999999999\" for the field \"season\"");
+        assert!(FilenameTemplate::parse("{episode:010}").is_ok());
+        assert_eq!(error("/./"), "The filename template \"/./\" produced an empty path");
+    }
+}
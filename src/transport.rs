@@ -0,0 +1,451 @@
+use std::{
+    fmt,
+    fs,
+    path::{Path, PathBuf},
+    task::Poll,
+};
+
+use anyhow::{Context, Result};
+
+use bytes::Bytes;
+
+use futures::{stream::{self, BoxStream}, StreamExt, TryStreamExt};
+
+use reqwest::{
+    Client, Method, StatusCode, Url,
+    header::{HeaderMap, HeaderName, HeaderValue, CONTENT_LENGTH, CONTENT_RANGE, RANGE},
+};
+
+use serde::{Deserialize, Serialize};
+
+
+/// Video bodies are cut to this size when recorded, HTML pages are smaller.
+const MAX_RECORDED_BODY: usize = 256 * 1024;
+
+
+/// Where `Net` gets its responses from.
+#[derive(Clone, Default)]
+pub enum TransportMode {
+    /// Straight from the network.
+    #[default]
+    Live,
+    /// From the network, saving every request/response pair to a fixtures directory.
+    Record(PathBuf),
+    /// From a fixtures directory written by `Record`, without touching the network.
+    Replay(PathBuf),
+}
+
+
+#[derive(Clone)]
+pub struct Transport {
+    client: Client,
+    mode: TransportMode,
+}
+
+
+#[derive(Clone)]
+pub struct HttpRequest {
+    pub method: Method,
+    pub url: Url,
+    pub headers: Vec<(HeaderName, String)>,
+    pub body: Option<String>,
+}
+
+
+pub struct HttpResponse {
+    url: Url,
+    status: StatusCode,
+    headers: HeaderMap,
+    body: BoxStream<'static, Result<Bytes>>,
+}
+
+
+#[derive(Debug)]
+pub struct StatusError {
+    pub url: Url,
+    pub status: StatusCode,
+}
+
+
+/// Copies the start of a recorded body and saves it as a fixture.
+struct Recorder {
+    /// The fixture path without its `.json`/`.body` extension.
+    path: PathBuf,
+    fixture: Fixture,
+    headers: HeaderMap,
+    body: Vec<u8>,
+    /// The body was cut at `MAX_RECORDED_BODY`.
+    truncated: bool,
+    /// The caller read the body to the end.
+    finished: bool,
+    saved: bool,
+}
+
+
+#[derive(Serialize, Deserialize)]
+struct Fixture {
+    method: String,
+    url: String,
+    range: Option<String>,
+    status: u16,
+    headers: Vec<(String, String)>,
+}
+
+
+impl Transport {
+    pub fn new(client: Client, mode: TransportMode) -> Self {
+        Self {
+            client,
+            mode,
+        }
+    }
+
+    pub async fn execute(&self, request: &HttpRequest) -> Result<HttpResponse> {
+        match &self.mode {
+            TransportMode::Live => self.fetch(request).await,
+            TransportMode::Record(dir) => self.record(request, dir).await,
+            TransportMode::Replay(dir) => Self::replay(request, dir),
+        }
+    }
+
+    async fn fetch(&self, request: &HttpRequest) -> Result<HttpResponse> {
+        let mut builder = self.client.request(request.method.clone(), request.url.clone());
+        for (name, value) in &request.headers {
+            builder = builder.header(name, value);
+        }
+        if let Some(body) = &request.body {
+            builder = builder.body(body.clone());
+        }
+
+        let response: reqwest::Response = builder.send().await?;
+        Ok(HttpResponse {
+            url: request.url.clone(),
+            status: response.status(),
+            headers: response.headers().clone(),
+            body: response.bytes_stream().map_err(anyhow::Error::from).boxed(),
+        })
+    }
+
+    /// Fetches the response and hands its full body to the caller, copying
+    /// the first `MAX_RECORDED_BODY` bytes into the fixture on the way, so
+    /// that only the fixture is cut short and never the downloaded file.
+    async fn record(&self, request: &HttpRequest, dir: &Path) -> Result<HttpResponse> {
+        let response: HttpResponse = self.fetch(request).await?;
+        fs::create_dir_all(dir)?;
+
+        let mut recorder: Recorder = Recorder {
+            path: dir.join(request.key()),
+            fixture: Fixture {
+                method: request.method.to_string(),
+                url: request.url.to_string(),
+                range: request.range(),
+                status: response.status.as_u16(),
+                headers: Vec::new(),
+            },
+            headers: response.headers.clone(),
+            body: Vec::new(),
+            truncated: false,
+            finished: false,
+            saved: false,
+        };
+
+        let mut body: BoxStream<'static, Result<Bytes>> = response.body;
+        Ok(HttpResponse {
+            url: response.url,
+            status: response.status,
+            headers: response.headers,
+            body: stream::poll_fn(move |cx| {
+                let next: Poll<Option<Result<Bytes>>> = body.poll_next_unpin(cx);
+                match &next {
+                    Poll::Ready(Some(Ok(chunk))) => recorder.push(chunk),
+                    Poll::Ready(None) => recorder.finished = true,
+                    _ => {},
+                }
+                next
+            }).boxed(),
+        })
+    }
+
+    fn replay(request: &HttpRequest, dir: &Path) -> Result<HttpResponse> {
+        let key: String = request.key();
+        let fixture: Fixture = serde_json::from_slice(
+            &fs::read(dir.join(format!("{key}.json")))
+                .context(format!("No recorded response for {} {}", request.method, request.url))?
+        )?;
+        let body: Vec<u8> = fs::read(dir.join(format!("{key}.body")))?;
+
+        let mut headers: HeaderMap = HeaderMap::new();
+        for (name, value) in fixture.headers {
+            headers.append(HeaderName::try_from(name)?, HeaderValue::try_from(value)?);
+        }
+
+        Ok(HttpResponse::new(request.url.clone(), StatusCode::from_u16(fixture.status)?, headers, body))
+    }
+}
+
+
+impl Recorder {
+    fn push(&mut self, chunk: &Bytes) {
+        if self.saved {
+            return;
+        }
+        self.body.extend_from_slice(chunk);
+        if self.body.len() >= MAX_RECORDED_BODY {
+            self.body.truncate(MAX_RECORDED_BODY);
+            self.truncated = true;
+            self.save();
+        }
+    }
+
+    /// Writes the fixture. A truncated body gets `Content-Length` and
+    /// `Content-Range` made to match the recorded part of it, and one read to
+    /// the end its actual length. The headers of HEAD responses and of
+    /// bodies the caller dropped unread stay as the server sent them.
+    fn save(&mut self) {
+        self.saved = true;
+
+        let length: u64 = self.body.len() as u64;
+        let head: bool = self.fixture.method == Method::HEAD.as_str();
+        if !head && self.truncated {
+            self.headers.insert(CONTENT_LENGTH, HeaderValue::from(length));
+            let range: Option<(u64, Option<u64>)> = parse_content_range(&self.headers)
+                .filter(|_| self.fixture.status == StatusCode::PARTIAL_CONTENT.as_u16());
+            if let Some((start, _)) = range {
+                let end: u64 = start + length;
+                if let Ok(value) = HeaderValue::from_str(format!("bytes {start}-{}/{end}", end.saturating_sub(1)).as_str()) {
+                    self.headers.insert(CONTENT_RANGE, value);
+                }
+            }
+        }
+        else if !head && self.finished && self.headers.contains_key(CONTENT_LENGTH) {
+            self.headers.insert(CONTENT_LENGTH, HeaderValue::from(length));
+        }
+        self.fixture.headers = self.headers
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+
+        let result: Result<()> = serde_json::to_string_pretty(&self.fixture)
+            .map_err(anyhow::Error::from)
+            .and_then(|fixture| Ok(fs::write(self.path.with_extension("json"), fixture)?))
+            .and_then(|_| Ok(fs::write(self.path.with_extension("body"), &self.body)?));
+        if let Err(e) = result {
+            eprintln!("Error saving the recorded response for {}: {e}", self.fixture.url);
+        }
+    }
+}
+
+
+impl Drop for Recorder {
+    /// Bodies shorter than `MAX_RECORDED_BODY` are saved once the caller is
+    /// done with them, whether it read them to the end or not.
+    fn drop(&mut self) {
+        if !self.saved {
+            self.save();
+        }
+    }
+}
+
+
+impl HttpRequest {
+    pub fn new(method: Method, url: Url) -> Self {
+        Self {
+            method,
+            url,
+            headers: Vec::new(),
+            body: None,
+        }
+    }
+
+    pub fn get(url: Url) -> Self {
+        Self::new(Method::GET, url)
+    }
+
+    pub fn post(url: Url) -> Self {
+        Self::new(Method::POST, url)
+    }
+
+    pub fn header<V: ToString>(mut self, name: HeaderName, value: V) -> Self {
+        self.headers.push((name, value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, body: String) -> Self {
+        self.body = Some(body);
+        self
+    }
+
+    fn range(&self) -> Option<String> {
+        self.headers
+            .iter()
+            .find(|(name, _)| name == RANGE)
+            .map(|(_, value)| value.clone())
+    }
+
+    /// A stable file name for the request: FNV-1a over everything that
+    /// changes the response (method, URL, range and body).
+    fn key(&self) -> String {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        let parts: [String; 4] = [
+            self.method.to_string(),
+            self.url.to_string(),
+            self.range().unwrap_or_default(),
+            self.body.clone().unwrap_or_default(),
+        ];
+        for byte in parts.join("\n").bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        format!("{hash:016x}")
+    }
+}
+
+
+impl HttpResponse {
+    fn new(url: Url, status: StatusCode, headers: HeaderMap, body: Vec<u8>) -> Self {
+        Self {
+            url,
+            status,
+            headers,
+            body: stream::once(async move { Ok(Bytes::from(body)) }).boxed(),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    pub fn content_length(&self) -> Option<u64> {
+        self.headers.get(CONTENT_LENGTH)?.to_str().ok()?.parse().ok()
+    }
+
+    /// Parses `Content-Range: bytes <start>-<end>/<total>` (or `bytes */<total>`)
+    /// into the first byte offset and the full size, if the server knows it.
+    pub fn content_range(&self) -> Option<(u64, Option<u64>)> {
+        parse_content_range(&self.headers)
+    }
+
+    pub fn error_for_status(self) -> Result<Self> {
+        if self.status.is_client_error() || self.status.is_server_error() {
+            return Err(StatusError { url: self.url, status: self.status }.into());
+        }
+        Ok(self)
+    }
+
+    pub fn bytes_stream(self) -> BoxStream<'static, Result<Bytes>> {
+        self.body
+    }
+
+    pub async fn text(self) -> Result<String> {
+        let body: Vec<u8> = self.body
+            .try_fold(Vec::new(), |mut body, chunk| async move {
+                body.extend_from_slice(&chunk);
+                Ok(body)
+            })
+            .await?;
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+}
+
+
+fn parse_content_range(headers: &HeaderMap) -> Option<(u64, Option<u64>)> {
+    let value: &str = headers.get(CONTENT_RANGE)?.to_str().ok()?;
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let total: Option<u64> = total.parse().ok();
+    let start: u64 = match range {
+        "*" => 0,
+        range => range.split_once('-')?.0.parse().ok()?,
+    };
+    Some((start, total))
+}
+
+
+impl fmt::Display for StatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HTTP status {} for url ({})", self.status, self.url)
+    }
+}
+
+
+impl std::error::Error for StatusError {}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records a response of a million bytes, feeds it `chunks` and
+    /// returns the saved headers and body length.
+    fn record(name: &str, method: Method, status: StatusCode, chunks: &[usize], finished: bool) -> (Vec<(String, String)>, usize) {
+        let path: PathBuf = std::env::temp_dir().join(format!("ando_2-recorder-{name}-{}", std::process::id()));
+        let mut headers: HeaderMap = HeaderMap::new();
+        headers.insert(CONTENT_LENGTH, HeaderValue::from(1_000_000));
+        if status == StatusCode::PARTIAL_CONTENT {
+            headers.insert(CONTENT_RANGE, HeaderValue::from_static("bytes 100-1000099/2000000"));
+        }
+
+        let mut recorder: Recorder = Recorder {
+            path: path.clone(),
+            fixture: Fixture {
+                method: method.to_string(),
+                url: String::from("http://127.0.0.1/video.mp4"),
+                range: None,
+                status: status.as_u16(),
+                headers: Vec::new(),
+            },
+            headers,
+            body: Vec::new(),
+            truncated: false,
+            finished: false,
+            saved: false,
+        };
+        for &size in chunks {
+            recorder.push(&Bytes::from(vec![0; size]));
+        }
+        recorder.finished = finished;
+        drop(recorder);
+
+        let fixture: Fixture = serde_json::from_slice(&fs::read(path.with_extension("json")).unwrap()).unwrap();
+        let body: usize = fs::read(path.with_extension("body")).unwrap().len();
+        fs::remove_file(path.with_extension("json")).unwrap();
+        fs::remove_file(path.with_extension("body")).unwrap();
+        (fixture.headers, body)
+    }
+
+    fn header(headers: &[(String, String)], name: &str) -> String {
+        headers.iter().find(|(n, _)| n == name).map(|(_, value)| value.clone()).unwrap_or_default()
+    }
+
+    #[test]
+    fn cuts_long_bodies_and_their_headers() {
+        let (headers, body) = record("cut", Method::GET, StatusCode::PARTIAL_CONTENT, &[200_000, 200_000], false);
+        assert_eq!(body, MAX_RECORDED_BODY);
+        assert_eq!(header(&headers, "content-length"), MAX_RECORDED_BODY.to_string());
+        assert_eq!(header(&headers, "content-range"), format!("bytes 100-{}/{}", 99 + MAX_RECORDED_BODY, 100 + MAX_RECORDED_BODY));
+    }
+
+    #[test]
+    fn keeps_the_headers_of_unread_and_head_responses() {
+        let (headers, body) = record("unread", Method::GET, StatusCode::OK, &[], false);
+        assert_eq!(body, 0);
+        assert_eq!(header(&headers, "content-length"), "1000000");
+
+        let (headers, _) = record("partly-read", Method::GET, StatusCode::PARTIAL_CONTENT, &[1000], false);
+        assert_eq!(header(&headers, "content-length"), "1000000");
+        assert_eq!(header(&headers, "content-range"), "bytes 100-1000099/2000000");
+
+        let (headers, _) = record("head", Method::HEAD, StatusCode::OK, &[], true);
+        assert_eq!(header(&headers, "content-length"), "1000000");
+    }
+
+    #[test]
+    fn matches_the_length_of_bodies_read_to_the_end() {
+        let (headers, body) = record("finished", Method::GET, StatusCode::OK, &[500, 500], true);
+        assert_eq!(body, 1000);
+        assert_eq!(header(&headers, "content-length"), "1000");
+    }
+}